@@ -0,0 +1,64 @@
+use crate::utils::{pattern, LitWrap};
+use regex::Regex;
+use syn::{spanned::Spanned, Error, Meta, MetaList, NestedMeta, Result};
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const NO_CACHE: &str = "no-cache";
+
+/// Cache-Control policy of the embedded files, resolved per file at compile time
+///
+/// ```text
+/// cache(
+///     immutable = "**/*.[hash].*",    # fingerprinted files, cached forever
+///     no_cache = "index.html",        # always revalidated
+///     max_age = 3600,                 # max-age in seconds for all the other files
+/// )
+/// ```
+/// `immutable` and `no_cache` can be repeated, and take a glob or a regex
+/// prefixed with `regex:`. `no_cache` wins when both rules match.
+#[derive(Default)]
+pub(crate) struct Cache {
+    immutable: Vec<Regex>,
+    no_cache: Vec<Regex>,
+    max_age: Option<u64>,
+}
+
+impl Cache {
+    pub fn parse(list: &MetaList) -> Result<Self> {
+        let mut cache = Cache::default();
+        for nm in &list.nested {
+            let nv = match nm {
+                NestedMeta::Meta(Meta::NameValue(nv)) => nv,
+                _ => return Err(Error::new(nm.span(), "expect key = value in cache")),
+            };
+
+            let lit = LitWrap { inner: &nv.lit };
+            let rules = match nv.path.get_ident() {
+                Some(id) if id == "max_age" => {
+                    cache.max_age = Some(lit.parse()?);
+                    continue;
+                }
+                Some(id) if id == "immutable" => &mut cache.immutable,
+                Some(id) if id == "no_cache" => &mut cache.no_cache,
+                _ => return Err(Error::new(nv.path.span(), "unknown cache rule")),
+            };
+
+            let rule = pattern(&lit.parse::<String>()?)
+                .map_err(|e| Error::new(nv.lit.span(), format!("invalid pattern: {}", e)))?;
+            rules.push(rule);
+        }
+
+        Ok(cache)
+    }
+
+    /// Cache-Control header value for the file, `None` means no header
+    pub fn header(&self, rel_path: &str) -> Option<String> {
+        if self.no_cache.iter().any(|r| r.is_match(rel_path)) {
+            Some(NO_CACHE.to_string())
+        } else if self.immutable.iter().any(|r| r.is_match(rel_path)) {
+            Some(IMMUTABLE.to_string())
+        } else {
+            self.max_age.map(|age| format!("public, max-age={}", age))
+        }
+    }
+}
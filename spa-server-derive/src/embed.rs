@@ -1,12 +1,34 @@
-use crate::utils;
-use proc_macro2::{Ident, Span, TokenStream};
+use crate::{filter::Filter, mime::MimeTypes, utils, Options};
+use proc_macro2::{Ident, Literal, Span, TokenStream};
 use quote::quote;
-use std::path::Path;
+use std::{collections::BTreeMap, fs, io::Write, path::Path, time::UNIX_EPOCH};
 use syn::Error;
 
-pub(crate) fn impl_embed(ident: &Ident, folder_path: &str, prefix: Option<&str>) -> TokenStream {
+/// file extensions worth to be precompressed, others (images, fonts...) are
+/// already compressed by their own format
+const COMPRESSIBLE: &[&str] = &[
+    "html",
+    "htm",
+    "css",
+    "js",
+    "mjs",
+    "json",
+    "map",
+    "svg",
+    "xml",
+    "txt",
+    "wasm",
+    "webmanifest",
+];
+
+pub(crate) fn impl_embed(
+    ident: &Ident,
+    folder_path: &str,
+    prefix: Option<&str>,
+    opt: &Options,
+) -> (TokenStream, Option<String>) {
     if !Path::new(&folder_path).exists() {
-        Error::new(
+        let error = Error::new(
             Span::call_site(),
             format!(
                 "static_files folder {} doest not exist. cwd: {:?}",
@@ -15,17 +37,43 @@ pub(crate) fn impl_embed(ident: &Ident, folder_path: &str, prefix: Option<&str>)
             ),
         )
         .into_compile_error();
+        return (error, None);
+    }
+
+    if cfg!(all(debug_assertions, not(feature = "debug-embed"))) {
+        return (
+            impl_dynamic(ident, folder_path, prefix, &opt.filter, &opt.mime),
+            None,
+        );
     }
 
     let mut match_values = Vec::new();
+    let mut encoded_values = Vec::new();
+    let mut metadata_values = Vec::new();
     let mut list_values = Vec::new();
+    let mut hashes = Vec::new();
+    let mut pages = Vec::new();
 
     for utils::FileEntry {
         rel_path,
         full_canonical_path,
-    } in utils::get_files(folder_path)
+    } in utils::get_files(folder_path, &opt.filter)
     {
+        let raw = fs::read(&full_canonical_path).expect("Could not read file");
+        let hash = utils::sha256_hex(&raw);
         match_values.push(embed_file(&rel_path, &full_canonical_path));
+        let (encoded, compressed_size) = embed_encoded(&rel_path, &raw);
+        encoded_values.push(encoded);
+        metadata_values.push(embed_metadata(
+            &rel_path,
+            &full_canonical_path,
+            &raw,
+            &hash,
+            compressed_size,
+            opt,
+        ));
+        hashes.push(format!("{}\0{}\n", rel_path, hash));
+        pages.push(rel_path.clone());
         list_values.push(if let Some(prefix) = prefix {
             format!("{}{}", prefix, rel_path)
         } else {
@@ -34,6 +82,8 @@ pub(crate) fn impl_embed(ident: &Ident, folder_path: &str, prefix: Option<&str>)
     }
 
     let array_len = list_values.len();
+    hashes.sort();
+    let build_hash = utils::sha256_hex(hashes.concat().as_bytes());
 
     let handle_prefix = if let Some(prefix) = prefix {
         quote! {
@@ -43,7 +93,14 @@ pub(crate) fn impl_embed(ident: &Ident, folder_path: &str, prefix: Option<&str>)
         TokenStream::new()
     };
 
-    quote! {
+    let content_type = content_type_fn(&opt.mime);
+    let page_values = page_table(&pages, prefix).into_iter().map(|(route, page)| {
+        quote! {
+            #route => Some(Cow::Borrowed(#page)),
+        }
+    });
+
+    let tokens = quote! {
         impl #ident {
             pub fn get(file_path: &str) -> Option<Cow<'static, [u8]>> {
                 #handle_prefix
@@ -53,6 +110,35 @@ pub(crate) fn impl_embed(ident: &Ident, folder_path: &str, prefix: Option<&str>)
                 }
             }
 
+            pub fn get_encoded(file_path: &str, encoding: Encoding) -> Option<&'static [u8]> {
+                #handle_prefix
+                match (file_path.replace("\\", "/").as_str(), encoding) {
+                    #(#encoded_values)*
+                    _ => None,
+                }
+            }
+
+            pub fn metadata(file_path: &str) -> Option<Metadata> {
+                #handle_prefix
+                match file_path.replace("\\", "/").as_str() {
+                    #(#metadata_values)*
+                    _ => None,
+                }
+            }
+
+            #content_type
+
+            pub fn page(route: &str) -> Option<Cow<'static, str>> {
+                match route.replace("\\", "/").trim_end_matches('/') {
+                    #(#page_values)*
+                    _ => None,
+                }
+            }
+
+            pub fn build_hash() -> Option<&'static str> {
+                Some(#build_hash)
+            }
+
             fn names() -> std::slice::Iter<'static, &'static str> {
                 const ITEMS: [&str; #array_len] = [#(#list_values),*];
                 ITEMS.iter()
@@ -68,13 +154,177 @@ pub(crate) fn impl_embed(ident: &Ident, folder_path: &str, prefix: Option<&str>)
                 #ident::get(file_path)
             }
 
+            fn get_encoded(file_path: &str, encoding: Encoding) -> Option<&'static [u8]> {
+                #ident::get_encoded(file_path, encoding)
+            }
+
+            fn metadata(file_path: &str) -> Option<Metadata> {
+                #ident::metadata(file_path)
+            }
+
+            fn content_type(file_path: &str) -> String {
+                #ident::content_type(file_path)
+            }
+
+            fn page(route: &str) -> Option<Cow<'static, str>> {
+                #ident::page(route)
+            }
+
+            fn build_hash() -> Option<&'static str> {
+                #ident::build_hash()
+            }
+
             fn iter() -> Filenames {
-                Filenames(#ident::names())
+                Filenames::Embedded(#ident::names())
+            }
+        }
+    };
+
+    (tokens, Some(build_hash))
+}
+
+/// debug build reads the files from the static_files folder on each call, so
+/// the frontend can be rebuilt without recompiling. nothing is precompressed
+/// and no metadata is available in this mode.
+fn impl_dynamic(
+    ident: &Ident,
+    folder_path: &str,
+    prefix: Option<&str>,
+    filter: &Filter,
+    mime: &MimeTypes,
+) -> TokenStream {
+    let content_type = content_type_fn(mime);
+    let folder_path =
+        utils::path_to_str(fs::canonicalize(folder_path).expect("Could not get canonicalize path"));
+    let include = filter.include.iter().map(|r| r.as_str());
+    let exclude = filter.exclude.iter().map(|r| r.as_str());
+    let filter = quote! {
+        &spa_server::Filter::new(&[#(#include),*], &[#(#exclude),*])
+    };
+
+    let (handle_prefix, add_prefix) = if let Some(prefix) = prefix {
+        (
+            quote! {
+                let file_path = file_path.strip_prefix(#prefix)?;
+            },
+            quote! {
+                .map(|x| Cow::from(format!("{}{}", #prefix, x)))
+            },
+        )
+    } else {
+        (TokenStream::new(), TokenStream::new())
+    };
+
+    quote! {
+        impl #ident {
+            pub fn get(file_path: &str) -> Option<Cow<'static, [u8]>> {
+                #handle_prefix
+                spa_server::read_file(#folder_path, &file_path.replace("\\", "/"), #filter)
+            }
+
+            pub fn get_encoded(_: &str, _: Encoding) -> Option<&'static [u8]> {
+                None
+            }
+
+            pub fn metadata(_: &str) -> Option<Metadata> {
+                None
+            }
+
+            #content_type
+
+            pub fn page(route: &str) -> Option<Cow<'static, str>> {
+                let route = route.replace("\\", "/");
+                let route = route.trim_end_matches('/');
+                if route.is_empty() {
+                    return None;
+                }
+
+                [format!("{}.html", route), format!("{}/index.html", route)]
+                    .iter()
+                    .find(|page| #ident::get(page).is_some())
+                    .map(|page| Cow::from(page.clone()))
+            }
+
+            pub fn build_hash() -> Option<&'static str> {
+                None
+            }
+
+            pub fn iter() -> impl Iterator<Item = Cow<'static, str>> {
+                spa_server::list_files(#folder_path, #filter)#add_prefix
             }
         }
+
+        impl Embed for #ident {
+            fn get(file_path: &str) -> Option<Cow<'static, [u8]>> {
+                #ident::get(file_path)
+            }
+
+            fn get_encoded(file_path: &str, encoding: Encoding) -> Option<&'static [u8]> {
+                #ident::get_encoded(file_path, encoding)
+            }
+
+            fn metadata(file_path: &str) -> Option<Metadata> {
+                #ident::metadata(file_path)
+            }
+
+            fn content_type(file_path: &str) -> String {
+                #ident::content_type(file_path)
+            }
+
+            fn page(route: &str) -> Option<Cow<'static, str>> {
+                #ident::page(route)
+            }
+
+            fn build_hash() -> Option<&'static str> {
+                #ident::build_hash()
+            }
+
+            fn iter() -> Filenames {
+                Filenames::Dynamic(#ident::iter().map(|x| x.into_owned()).collect::<Vec<_>>().into_iter())
+            }
+        }
+    }
+}
+
+/// Content-Type by the file extension, the `mime(...)` overrides and the
+/// defaults apply to the files read from disk or extracted as well
+fn content_type_fn(mime: &MimeTypes) -> TokenStream {
+    let (exts, mimes): (Vec<_>, Vec<_>) = mime.table().into_iter().unzip();
+    quote! {
+        pub fn content_type(file_path: &str) -> String {
+            spa_server::content_type(file_path, &[#((#exts, #mimes)),*])
+        }
     }
 }
 
+/// route to pre-rendered page table, `<route>.html` wins over
+/// `<route>/index.html`. the root index.html is the SPA shell, not a page
+fn page_table(files: &[String], prefix: Option<&str>) -> BTreeMap<String, String> {
+    let prefix = prefix.unwrap_or_default();
+    let mut table = BTreeMap::new();
+    for file in files {
+        if let Some(route) = file.strip_suffix("/index.html") {
+            table.insert(
+                format!("{}{}", prefix, route),
+                format!("{}{}", prefix, file),
+            );
+        }
+    }
+
+    for file in files {
+        if let Some(route) = file.strip_suffix(".html") {
+            if route != "index" && !route.ends_with("/index") {
+                table.insert(
+                    format!("{}{}", prefix, route),
+                    format!("{}{}", prefix, file),
+                );
+            }
+        }
+    }
+
+    table
+}
+
 fn embed_file(rel_path: &str, full_canonical_path: &str) -> TokenStream {
     quote! {
         #rel_path => {
@@ -85,3 +335,138 @@ fn embed_file(rel_path: &str, full_canonical_path: &str) -> TokenStream {
         }
     }
 }
+
+fn embed_metadata(
+    rel_path: &str,
+    full_canonical_path: &str,
+    raw: &[u8],
+    hash: &str,
+    compressed_size: Option<usize>,
+    opt: &Options,
+) -> TokenStream {
+    let (mime, charset) = opt.mime.guess(rel_path);
+    let charset = option_tokens(charset);
+    let size = raw.len();
+    let compressed_size = option_tokens(compressed_size);
+    let last_modified = option_tokens(
+        fs::metadata(full_canonical_path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs()),
+    );
+    let cache_control = option_tokens(opt.cache.header(rel_path));
+
+    quote! {
+        #rel_path => Some(Metadata {
+            hash: #hash,
+            mime: #mime,
+            charset: #charset,
+            size: #size,
+            compressed_size: #compressed_size,
+            last_modified: #last_modified,
+            cache_control: #cache_control,
+        }),
+    }
+}
+
+fn option_tokens<T: quote::ToTokens>(value: Option<T>) -> TokenStream {
+    match value {
+        Some(v) => quote! { Some(#v) },
+        None => quote! { None },
+    }
+}
+
+/// returns the match arms of the precompressed variants, and the size of the
+/// smallest one
+fn embed_encoded(rel_path: &str, raw: &[u8]) -> (TokenStream, Option<usize>) {
+    let compressible = Path::new(rel_path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| COMPRESSIBLE.contains(&e.to_ascii_lowercase().as_str()))
+        .unwrap_or(false);
+    if !compressible {
+        return (TokenStream::new(), None);
+    }
+
+    let mut tokens = TokenStream::new();
+    let mut compressed_size = None;
+    // only keep the variant which is actually smaller than the raw file
+    if let Some(gz) = gzip(raw).filter(|gz| gz.len() < raw.len()) {
+        compressed_size = Some(gz.len());
+        let gz = Literal::byte_string(&gz);
+        tokens.extend(quote! {
+            (#rel_path, Encoding::Gzip) => Some(&#gz[..]),
+        });
+    }
+
+    if let Some(br) = brotli(raw).filter(|br| br.len() < raw.len()) {
+        compressed_size = Some(compressed_size.map_or(br.len(), |gz: usize| gz.min(br.len())));
+        let br = Literal::byte_string(&br);
+        tokens.extend(quote! {
+            (#rel_path, Encoding::Brotli) => Some(&#br[..]),
+        });
+    }
+
+    (tokens, compressed_size)
+}
+
+fn gzip(raw: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(raw).ok()?;
+    encoder.finish().ok()
+}
+
+fn brotli(raw: &[u8]) -> Option<Vec<u8>> {
+    let mut encoded = Vec::new();
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut encoded, 4096, 11, 22);
+        encoder.write_all(raw).ok()?;
+    }
+
+    Some(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(files: &[&str], prefix: Option<&str>) -> Vec<(String, String)> {
+        let files: Vec<_> = files.iter().map(|f| f.to_string()).collect();
+        page_table(&files, prefix).into_iter().collect()
+    }
+
+    fn pair(route: &str, page: &str) -> (String, String) {
+        (route.to_string(), page.to_string())
+    }
+
+    #[test]
+    fn html_wins_over_index() {
+        assert_eq!(
+            table(&["about/index.html", "about.html", "docs/index.html"], None),
+            vec![pair("about", "about.html"), pair("docs", "docs/index.html")]
+        );
+        // the order of the listing doesn't matter
+        assert_eq!(
+            table(&["about.html", "about/index.html"], None),
+            vec![pair("about", "about.html")]
+        );
+    }
+
+    #[test]
+    fn root_index_excluded() {
+        assert_eq!(
+            table(&["index.html", "blog/index.html", "main.js"], None),
+            vec![pair("blog", "blog/index.html")]
+        );
+        assert!(table(&["index.html"], None).is_empty());
+    }
+
+    #[test]
+    fn prefixed() {
+        assert_eq!(
+            table(&["index.html", "about.html"], Some("/static/")),
+            vec![pair("/static/about", "/static/about.html")]
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use crate::utils::{pattern, LitWrap};
+use regex::Regex;
+use syn::{spanned::Spanned, Error, MetaList, NestedMeta, Result};
+
+/// which files of the static_files folder get embedded
+///
+/// ```text
+/// include("**/*.{html,js,css}", "assets/**"),    # only embed matched files
+/// exclude("**/.DS_Store", "**/stats.json"),       # never embed matched files
+/// strip_source_maps,                              # exclude **/*.map in release build
+/// ```
+/// patterns are globs relative to the static_files folder, or a regex prefixed
+/// with `regex:`. without `include` all the files are included.
+#[derive(Default)]
+pub(crate) struct Filter {
+    pub include: Vec<Regex>,
+    pub exclude: Vec<Regex>,
+}
+
+impl Filter {
+    pub fn parse_patterns(list: &MetaList) -> Result<Vec<Regex>> {
+        let mut patterns = Vec::new();
+        for nm in &list.nested {
+            let lit = match nm {
+                NestedMeta::Lit(lit) => lit,
+                _ => return Err(Error::new(nm.span(), "expect a pattern string")),
+            };
+
+            let p = LitWrap { inner: lit }.parse::<String>()?;
+            patterns.push(
+                pattern(&p)
+                    .map_err(|e| Error::new(lit.span(), format!("invalid pattern: {}", e)))?,
+            );
+        }
+
+        Ok(patterns)
+    }
+
+    /// source maps are only stripped from release build, so they are still
+    /// available while debugging
+    pub fn strip_source_maps(&mut self) {
+        if cfg!(not(debug_assertions)) {
+            self.exclude
+                .push(pattern("**/*.map").expect("source map pattern is valid"));
+        }
+    }
+
+    pub fn matches(&self, rel_path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|r| r.is_match(rel_path)))
+            && !self.exclude.iter().any(|r| r.is_match(rel_path))
+    }
+}
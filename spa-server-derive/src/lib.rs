@@ -1,15 +1,24 @@
+mod cache;
 mod embed;
+mod filter;
+mod mime;
 mod utils;
 
+use cache::Cache;
 use embed::impl_embed;
+use filter::Filter;
+use mime::MimeTypes;
 use proc_macro2::{Span, TokenStream};
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::{
-    parse_macro_input, spanned::Spanned, DeriveInput, Error, FnArg, Meta, NestedMeta, Pat, Path,
-    Result,
+    parse_macro_input, spanned::Spanned, DeriveInput, Error, FnArg, Ident, Meta, MetaList,
+    NestedMeta, Pat, Path, Result,
 };
 use utils::{FromLit, LitWrap};
 
+/// the default SPA entry page
+const INDEX_FILE: &str = "index.html";
+
 #[proc_macro_derive(SPAServer, attributes(spa_server))]
 pub fn derive_spa_server(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
@@ -74,10 +83,34 @@ fn expand(input: DeriveInput) -> Result<TokenStream> {
                 }
             });
 
-            opt.static_files = get_name_value(metas.clone(), "static_files").ok_or(Error::new(
-                Span::call_site(),
-                "must set static files path in attribute",
-            ))?;
+            if let Some(static_files) = get_name_value(metas.clone(), "static_files") {
+                opt.mounts.push(Mount {
+                    dir: static_files,
+                    at: "/".to_string(),
+                    index: Some(INDEX_FILE.to_string()),
+                });
+            }
+
+            if get_path(metas.clone(), "strip_source_maps") {
+                opt.filter.strip_source_maps();
+            }
+
+            if get_path(metas.clone(), "extract") {
+                opt.extract = Some(None);
+            }
+
+            opt.manifest = get_name_value(metas.clone(), "manifest");
+            opt.shutdown_timeout = get_name_value(metas.clone(), "shutdown_timeout");
+            if let Some(base_path) = get_name_value(metas.clone(), "base_path") {
+                opt.base_path = base_path;
+            }
+
+            if get_path(metas.clone(), "csp") {
+                opt.csp = Some(Csp::default());
+            }
+            if get_path(metas.clone(), "build_id") {
+                opt.build_id = Some(BuildId::default());
+            }
 
             opt.cors = get_path(metas.clone(), "cors");
             if !opt.cors {
@@ -159,6 +192,79 @@ fn expand(input: DeriveInput) -> Result<TokenStream> {
                                 if !identity.name.is_empty() && identity.age != 0 {
                                     opt.identity = Some(identity);
                                 }
+                            } else if id == "cache" {
+                                opt.cache = Cache::parse(l)?;
+                            } else if id == "assets" {
+                                for nm in &l.nested {
+                                    match nm {
+                                        NestedMeta::Meta(Meta::List(ml))
+                                            if ml.path.is_ident("mount")
+                                                || ml.path.is_ident("spa") =>
+                                        {
+                                            let metas = ml.nested.iter().filter_map(|x| {
+                                                if let NestedMeta::Meta(m) = x {
+                                                    Some(m)
+                                                } else {
+                                                    None
+                                                }
+                                            });
+                                            let dir = get_name_value(metas.clone(), "dir")
+                                                .ok_or_else(|| {
+                                                    Error::new(ml.span(), "must set dir of mount")
+                                                })?;
+                                            let at = get_name_value(metas.clone(), "at")
+                                                .ok_or_else(|| {
+                                                    Error::new(ml.span(), "must set at of mount")
+                                                })?;
+                                            // a spa has its own index page for the
+                                            // client side routes under its path
+                                            let index = if ml.path.is_ident("spa") {
+                                                Some(
+                                                    get_name_value(metas, "index")
+                                                        .unwrap_or_else(|| INDEX_FILE.to_string()),
+                                                )
+                                            } else {
+                                                None
+                                            };
+                                            opt.mounts.push(Mount { dir, at, index });
+                                        }
+                                        _ => {
+                                            return Err(Error::new(
+                                                nm.span(),
+                                                "expect mount(dir = \"...\", at = \"...\") or spa(dir = \"...\", at = \"...\", index = \"...\")",
+                                            ))
+                                        }
+                                    }
+                                }
+                            } else if id == "extract" {
+                                let metas = l.nested.iter().filter_map(|x| {
+                                    if let NestedMeta::Meta(m) = x {
+                                        Some(m)
+                                    } else {
+                                        None
+                                    }
+                                });
+                                opt.extract = Some(get_name_value(metas, "dir"));
+                            } else if id == "fallback" {
+                                opt.fallback = Fallback::parse(l)?;
+                            } else if id == "error_pages" {
+                                opt.error_pages = Some(ErrorPages::parse(l)?);
+                            } else if id == "pwa" {
+                                opt.pwa = Some(Pwa::parse(l)?);
+                            } else if id == "tls" {
+                                opt.tls = Some(Tls::parse(l)?);
+                            } else if id == "build_id" {
+                                opt.build_id = Some(BuildId::parse(l)?);
+                            } else if id == "csp" {
+                                opt.csp = Some(Csp::parse(l)?);
+                            } else if id == "config" {
+                                opt.config = Some(IndexConfig::parse(l)?);
+                            } else if id == "mime" {
+                                opt.mime.parse_override(l)?;
+                            } else if id == "include" {
+                                opt.filter.include = Filter::parse_patterns(l)?;
+                            } else if id == "exclude" {
+                                opt.filter.exclude.extend(Filter::parse_patterns(l)?);
                             }
                         }
                     }
@@ -169,9 +275,9 @@ fn expand(input: DeriveInput) -> Result<TokenStream> {
     }
 
     let mut services = Vec::new();
-    for api in opt.apis {
-        let api_list = api.path;
-        match api.prefix {
+    for api in &opt.apis {
+        let api_list = &api.path;
+        match &api.prefix {
             Some(p) => {
                 services.push(quote! {
                     .service(
@@ -190,69 +296,944 @@ fn expand(input: DeriveInput) -> Result<TokenStream> {
         }
     }
 
-    let cors = if opt.cors {
-        quote! { .wrap(spa_server::re_export::Cors::permissive()) }
+    // the runtime settings override the attribute, and are the defaults of
+    // run_from_config
+    let mut config_defaults = Vec::new();
+    let (cors_settings, cors) = if opt.cors {
+        config_defaults.push(quote! { cors_origins: Some(Vec::new()), });
+        (
+            quote! { let cors_origins = settings.cors_origins.clone(); },
+            quote! {
+                .wrap(cors_origins.iter().fold(
+                    spa_server::re_export::Cors::permissive(),
+                    |cors, origin| cors.allowed_origin(origin),
+                ))
+            },
+        )
     } else {
-        TokenStream::new()
+        (TokenStream::new(), TokenStream::new())
     };
 
-    let identity = if let Some(id) = opt.identity {
-        let name = id.name;
+    let (identity_settings, identity) = if let Some(id) = &opt.identity {
+        let name = &id.name;
         let age = id.age;
+        config_defaults.push(quote! {
+            identity: Some(spa_server::IdentityConfig {
+                name: #name.to_string(),
+                max_age: #age,
+                secure: false,
+                key: None,
+            }),
+        });
+        (
+            quote! {
+                let identity_name = settings
+                    .identity_name
+                    .clone()
+                    .unwrap_or_else(|| #name.to_string());
+                let identity_max_age = settings.identity_max_age.unwrap_or(#age);
+                let identity_secure = settings.identity_secure.unwrap_or(false);
+                let identity_key = settings.identity_key.clone().unwrap_or_else(|| vec![0; 32]);
+            },
+            quote! {
+                .wrap(spa_server::re_export::IdentityService::new(
+                    spa_server::re_export::CookieIdentityPolicy::new(&identity_key)
+                        .name(&identity_name)
+                        .max_age_time(spa_server::Duration::minutes(identity_max_age))
+                        .http_only(true)
+                        .secure(identity_secure)
+                ))
+            },
+        )
+    } else {
+        (TokenStream::new(), TokenStream::new())
+    };
+
+    if opt.mounts.is_empty() {
+        return Err(Error::new(
+            Span::call_site(),
+            "must set static_files or assets path in attribute",
+        ));
+    }
+
+    // the first mount is embedded into the struct itself, the others get their
+    // own hidden struct, so every mount has its own embedded table
+    let mut embed_tokens = Vec::new();
+    let mut mounts = Vec::new();
+    let mut build_hashes = Vec::new();
+    let mut manifest_mounts = Vec::new();
+    for (i, mount) in opt.mounts.iter().enumerate() {
+        let at = mount.at.trim_end_matches('/');
+        if mounts.iter().any(|(m, _, _)| m == at) {
+            return Err(Error::new(
+                Span::call_site(),
+                format!("assets mounted at {} more than once", mount.at),
+            ));
+        }
+
+        let ident = if i == 0 {
+            name.clone()
+        } else {
+            let ident = format_ident!("__{}Mount{}", name, i);
+            embed_tokens.push(quote! {
+                #[doc(hidden)]
+                #[allow(non_camel_case_types)]
+                struct #ident;
+            });
+            ident
+        };
+
+        // the root mount always serves a SPA
+        let index = mount
+            .index
+            .clone()
+            .or_else(|| Some(INDEX_FILE.to_string()).filter(|_| at.is_empty()));
+        if let Some(index) = &index {
+            let page = std::path::Path::new(&mount.dir).join(index);
+            if std::path::Path::new(&mount.dir).exists() && !page.is_file() {
+                return Err(Error::new(
+                    Span::call_site(),
+                    format!("index page {} does not exist", page.display()),
+                ));
+            }
+        }
+
+        let (tokens, build_hash) = impl_embed(&ident, &mount.dir, None, &opt);
+        embed_tokens.push(tokens);
+        build_hashes.push(build_hash.map(|h| (at.to_string(), h)));
+        manifest_mounts.push((if at.is_empty() { "/" } else { at }, ident.clone()));
+        mounts.push((at.to_string(), ident, index));
+    }
+
+    // build hash of all the mounts, None when any of them is read from disk
+    let build_hash = build_hashes
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .map(|mut hashes| {
+            if hashes.len() == 1 {
+                hashes.remove(0).1
+            } else {
+                hashes.sort();
+                let all = hashes
+                    .iter()
+                    .map(|(at, h)| format!("{}\0{}\n", at, h))
+                    .collect::<String>();
+                utils::sha256_hex(all.as_bytes())
+            }
+        });
+    let build_hash = match build_hash {
+        Some(h) => quote! { Some(#h) },
+        None => quote! { None },
+    };
+    let manifest_entries = manifest_mounts.iter().map(|(at, ident)| {
         quote! {
-            .wrap(spa_server::re_export::IdentityService::new(
-                spa_server::re_export::CookieIdentityPolicy::new(&[0; 32])
-                    .name(#name)
-                    .max_age_time(spa_server::Duration::minutes(#age))
-                    .http_only(true)
-                    .secure(false)
-            ))
+            files.extend(spa_server::manifest_entries::<#ident>(#at));
+        }
+    });
+    let manifest_service = match &opt.manifest {
+        Some(path) => quote! {
+            .service(spa_server::manifest_service(#path, #name::asset_manifest))
+        },
+        None => TokenStream::new(),
+    };
+
+    // longer mount path first, so /docs/api is not swallowed by /docs, and the
+    // root mount comes last
+    mounts.sort_by_key(|(at, _, _)| std::cmp::Reverse(at.len()));
+    let mut extract_assets = Vec::new();
+    let mut keep_extracted = TokenStream::new();
+    let mut asset_services = Vec::new();
+    if let Some(dir) = &opt.extract {
+        // release the files on disk and serve them from there. the guards are
+        // moved into the app factory, which the server holds until it stops,
+        // so the extracted folders are removed once the server completes
+        let dir = match dir {
+            Some(dir) => quote! { Some(#dir) },
+            None => quote! { None },
+        };
+
+        let mut guards = Vec::new();
+        for (i, (at, ident, index)) in mounts.iter().enumerate() {
+            let guard = format_ident!("assets_{}", i);
+            guards.push(guard.clone());
+            let path = format_ident!("assets_path_{}", i);
+            extract_assets.push(quote! {
+                let #guard = spa_server::release_asset::<#ident>(#dir)?;
+                let #path = #guard.path().to_path_buf();
+            });
+
+            let index = match index {
+                Some(index) => quote! { Some(#index) },
+                None => quote! { None },
+            };
+            asset_services.push(quote! {
+                .service(spa_server::extracted_service::<#ident>(#at, #path.clone(), #index))
+            });
+        }
+        extract_assets.push(quote! {
+            let extracted = std::sync::Arc::new((#(#guards,)*));
+        });
+        keep_extracted = quote! {
+            let _ = &extracted;
+        };
+    } else {
+        for (at, ident, index) in &mounts {
+            asset_services.push(match index {
+                Some(index) => quote! {
+                    .service(spa_server::spa_service::<#ident>(#at, #index))
+                },
+                None => quote! {
+                    .service(spa_server::asset_service::<#ident>(#at))
+                },
+            });
+        }
+    }
+
+    // every api prefix is excluded from the SPA fallback by default
+    let exclude = opt.fallback.exclude.clone().unwrap_or_else(|| {
+        opt.apis
+            .iter()
+            .filter_map(|api| api.prefix.clone())
+            .collect()
+    });
+    let matcher = opt
+        .fallback
+        .matcher
+        .clone()
+        .unwrap_or_else(|| format_ident!("NoExtension"));
+    let fallback = quote! {
+        spa_server::Fallback::new(
+            vec![#(format!("{}{}", base_path, #exclude)),*],
+            spa_server::Matcher::#matcher,
+        )
+    };
+
+    // the index pages are rendered through a template for the runtime config,
+    // which is read once at startup, and the per request CSP nonce
+    let mut steps = Vec::new();
+    if let Some(config) = &opt.config {
+        if let Some(placeholder) = &config.placeholder {
+            steps.push(quote! { .placeholder(#placeholder) });
+        }
+        if let Some(prefix) = &config.env_prefix {
+            steps.push(quote! { .env(#prefix) });
         }
+        if let Some(method) = &config.method {
+            steps.push(quote! { .config(&self.#method())? });
+        }
+    }
+    if let Some(csp) = &opt.csp {
+        let policy = match &csp.policy {
+            Some(p) => quote! { #p },
+            None => quote! { spa_server::DEFAULT_CSP },
+        };
+        let report_only = csp.report_only;
+        steps.push(quote! { .csp(#policy, #report_only) });
+    }
+
+    // the embedded struct and the folder of the root mount, the error pages and
+    // the service worker are picked out of it
+    let root = mounts
+        .iter()
+        .find(|(at, _, _)| at.is_empty())
+        .map(|(_, ident, _)| ident)
+        .zip(
+            opt.mounts
+                .iter()
+                .find(|m| m.at.trim_end_matches('/').is_empty())
+                .map(|m| m.dir.as_str()),
+        );
+    let root_file = |option: &str, file: &String| {
+        let (root, dir) = root.ok_or_else(|| {
+            Error::new(Span::call_site(), format!("{} needs static_files", option))
+        })?;
+        if std::path::Path::new(dir).exists()
+            && !utils::get_files(dir, &opt.filter).any(|f| &f.rel_path == file)
+        {
+            return Err(Error::new(
+                Span::call_site(),
+                format!("{} file {} is not in {}", option, file, dir),
+            ));
+        }
+
+        Ok(root)
+    };
+
+    // error pages are served out of the root mount
+    let mut error_pages = TokenStream::new();
+    let mut error_pages_data = TokenStream::new();
+    let mut error_page = TokenStream::new();
+    if let Some(pages) = &opt.error_pages {
+        let mut root = None;
+        for page in pages.not_found.iter().chain(&pages.server_error) {
+            root = Some(root_file("error_pages", page)?);
+        }
+
+        let not_found = option_str(&pages.not_found);
+        let server_error = option_str(&pages.server_error);
+        if let Some(root) = root {
+            error_pages = quote! {
+                let error_pages = web::Data::new(
+                    spa_server::ErrorPages::new::<#root>(#not_found, #server_error)
+                );
+            };
+            error_pages_data = quote! { .app_data(error_pages.clone()) };
+            // innermost, so the other middlewares see the page like any
+            // response
+            error_page = quote! { .wrap_fn(spa_server::error_page) };
+        }
+    }
+
+    let mut pwa = TokenStream::new();
+    let mut pwa_data = TokenStream::new();
+    let mut precache_service = TokenStream::new();
+    if let Some(opt_pwa) = &opt.pwa {
+        root_file("pwa", &opt_pwa.service_worker)?;
+        let service_worker = &opt_pwa.service_worker;
+        let scope = option_str(&opt_pwa.scope);
+        pwa = quote! {
+            let pwa = web::Data::new(spa_server::Pwa::new(&base_path, #service_worker, #scope));
+        };
+        pwa_data = quote! { .app_data(pwa.clone()) };
+        if let Some(path) = &opt_pwa.precache {
+            precache_service = quote! {
+                .service(spa_server::precache_service(#path, #name::asset_manifest))
+            };
+        }
+    }
+
+    let mut build_id = TokenStream::new();
+    let mut build_id_data = TokenStream::new();
+    let mut build_id_service = TokenStream::new();
+    if let Some(opt_build_id) = &opt.build_id {
+        let path = &opt_build_id.path;
+        let header = &opt_build_id.header;
+        build_id = quote! {
+            let build_id = web::Data::new(spa_server::BuildId::new(#build_hash, #header));
+        };
+        build_id_data = quote! { .app_data(build_id.clone()) };
+        build_id_service = quote! { .service(spa_server::build_id_service(#path)) };
+    }
+
+    let tls = match &opt.tls {
+        Some(tls) => {
+            let cert = &tls.cert;
+            let key = &tls.key;
+            let reload = tls.reload;
+            quote! { Some(spa_server::TlsConfig::new(#cert, #key).reload_on_sighup(#reload)) }
+        }
+        None => quote! { None },
+    };
+    // the rustls config is shared by all the https listeners, so the
+    // certificate is loaded and reloaded once
+    let (tls_config, bind_https) = if cfg!(feature = "tls") {
+        (
+            quote! {
+                let tls_config =
+                    spa_server::https_config(settings.tls.as_ref(), &settings.listeners)?;
+            },
+            quote! {
+                spa_server::Listener::Https(addr) => {
+                    let config = tls_config
+                        .clone()
+                        .ok_or("https listener without tls certificate")?;
+                    server.bind_rustls(addr, config)?
+                }
+            },
+        )
     } else {
-        TokenStream::new()
+        (
+            TokenStream::new(),
+            quote! {
+                spa_server::Listener::Https(_) => {
+                    return Err("https listener needs the tls feature of spa-server".into())
+                }
+            },
+        )
     };
 
-    let embed_tokens = impl_embed(name, &opt.static_files, None);
+    let shutdown_timeout = match opt.shutdown_timeout {
+        Some(secs) => quote! { Some(std::time::Duration::from_secs(#secs)) },
+        None => quote! { None },
+    };
 
+    let base_path = &opt.base_path;
     Ok(quote! {
         use spa_server::re_export::{
-            App, HttpServer, rt::System, web, Files
+            App, HttpServer, rt::System, web
         };
-        use spa_server::{Embed, Filenames};
+        use spa_server::{Embed, Encoding, Filenames, Metadata};
         use std::borrow::Cow;
 
         impl #name {
             pub async fn run(self, port: u16) -> Result<(), Box<dyn std::error::Error>> {
-                let root_path = spa_server::release_asset::<#name>()?;
+                self.builder().port(port).run().await
+            }
+
+            /// like `run`, but serves everything under `base_path` instead of
+            /// the one set in the attribute
+            pub async fn run_with_base_path(
+                self,
+                port: u16,
+                base_path: &str,
+            ) -> Result<(), Box<dyn std::error::Error>> {
+                self.builder().port(port).base_path(base_path).run().await
+            }
+
+            /// like `run`, but the requests out of the apis are forwarded to
+            /// the frontend dev server listening at `127.0.0.1:dev_port`,
+            /// `SPA_DEV_PROXY=<dev_port>` does the same for `run`
+            pub async fn run_with_dev_proxy(
+                self,
+                port: u16,
+                dev_port: u16,
+            ) -> Result<(), Box<dyn std::error::Error>> {
+                self.builder().port(port).dev_proxy(dev_port).run().await
+            }
+
+            /// like `run`, but every one of `listeners` serves the app, e.g.
+            /// `tcp://127.0.0.1:8080`, `tcp://[::]:8080`, `https://[::]:8443`
+            /// or `unix:///run/app.sock`
+            pub async fn run_with_listeners<I, L>(
+                self,
+                listeners: I,
+            ) -> Result<(), Box<dyn std::error::Error>>
+            where
+                I: IntoIterator<Item = L>,
+                L: AsRef<str>,
+            {
+                self.start_with_listeners(listeners)?.await?;
+                Ok(())
+            }
+
+            /// like `run_with_listeners`, but the `https://` listeners use
+            /// `tls` instead of the certificate set in the attribute
+            pub async fn run_with_tls<I, L>(
+                self,
+                listeners: I,
+                tls: spa_server::TlsConfig,
+            ) -> Result<(), Box<dyn std::error::Error>>
+            where
+                I: IntoIterator<Item = L>,
+                L: AsRef<str>,
+            {
+                listeners
+                    .into_iter()
+                    .fold(self.builder().tls(tls), |b, l| b.listen(l.as_ref()))
+                    .run()
+                    .await
+            }
+
+            /// like `run`, but returns as soon as the server listens, with a
+            /// handle to stop it and await its end. must be called inside the
+            /// actix runtime, e.g. in a `#[spa_server::main]` function
+            pub fn start(
+                self,
+                port: u16,
+            ) -> Result<spa_server::ServerHandle, Box<dyn std::error::Error>> {
+                self.builder().port(port).start()
+            }
+
+            /// `start` with the listeners of `run_with_listeners`
+            pub fn start_with_listeners<I, L>(
+                self,
+                listeners: I,
+            ) -> Result<spa_server::ServerHandle, Box<dyn std::error::Error>>
+            where
+                I: IntoIterator<Item = L>,
+                L: AsRef<str>,
+            {
+                listeners
+                    .into_iter()
+                    .fold(self.builder(), |b, l| b.listen(l.as_ref()))
+                    .start()
+            }
+
+            /// like `run`, but the port, bind address, base path, cors origins,
+            /// identity cookie and log level come from the `SPA_*` env vars
+            /// and the TOML or JSON file at `SPA_CONFIG`, over the attribute.
+            /// every invalid key is reported, the effective config is logged
+            /// with the secrets redacted
+            pub async fn run_from_config(self) -> Result<(), Box<dyn std::error::Error>> {
+                let config = spa_server::RuntimeConfig {
+                    base_path: #base_path.to_string(),
+                    #(#config_defaults)*
+                    ..Default::default()
+                }
+                .load()?;
+
+                config.apply(self.builder()).run().await
+            }
+
+            /// tune the server at runtime, the worker count, keep-alive,
+            /// timeouts, connection limits, payload limit and listeners
+            pub fn builder(self) -> spa_server::ServerBuilder<Self> {
+                spa_server::ServerBuilder::new(self, #base_path, #tls, #shutdown_timeout)
+            }
+
+            /// all the embedded files with their size, hash and mime type, and
+            /// the build hash of the whole set
+            pub fn asset_manifest() -> spa_server::AssetManifest {
+                let mut files = Vec::new();
+                #(#manifest_entries)*
+                files.sort_by(|a, b| a.path.cmp(&b.path));
+
+                spa_server::AssetManifest {
+                    build_hash: #build_hash,
+                    files,
+                }
+            }
+        }
+
+        impl spa_server::Serve for #name {
+            fn serve(
+                self,
+                settings: spa_server::ServerSettings,
+            ) -> Result<spa_server::ServerHandle, Box<dyn std::error::Error>> {
+                let base_path = spa_server::base_path(&settings.base_path);
+                let dev_proxy = settings.dev_proxy;
+                let payload_limit = settings.payload_limit;
+                #cors_settings
+                #identity_settings
+                #(#extract_assets)*
+                let template = web::Data::new(
+                    spa_server::IndexTemplate::default()
+                        #(#steps)*
+                        .base_path(&base_path)
+                );
+                #error_pages
+                #pwa
+                #build_id
                 let data = web::Data::new(self);
+                let fallback = web::Data::new(#fallback);
 
-                HttpServer::new(move || {
+                let mut server = HttpServer::new(move || {
+                    #keep_extracted
                     App::new()
+                        #error_page
                         #identity
                         #cors
-                        #(#services)*
-                        .data(root_path.clone())
-                        .service(spa_server::index)
-                        .service(Files::new("/", root_path.clone()).index_file("index.html"))
-                })
-                .bind(format!("0.0.0.0:{}", port))?
-                .run()
-                .await?;
+                        .service(
+                            web::scope(&base_path)
+                                .service(spa_server::base_redirect())
+                                #(#services)*
+                                #manifest_service
+                                #precache_service
+                                #build_id_service
+                                .configure(|cfg| match dev_proxy {
+                                    Some(dev_port) => {
+                                        cfg.service(spa_server::proxy_service(dev_port));
+                                    }
+                                    None => {
+                                        cfg #(#asset_services)*;
+                                    }
+                                })
+                        )
+                        .app_data(fallback.clone())
+                        .app_data(template.clone())
+                        #error_pages_data
+                        #pwa_data
+                        #build_id_data
+                        .configure(|cfg| {
+                            if let Some(limit) = payload_limit {
+                                cfg.app_data(web::PayloadConfig::new(limit))
+                                    .app_data(web::JsonConfig::default().limit(limit));
+                            }
+                        })
+                        .default_service(web::route().to(spa_server::not_found))
+                });
+                if let Some(workers) = settings.workers {
+                    server = server.workers(workers);
+                }
+                if let Some(keep_alive) = settings.keep_alive {
+                    // whole seconds, rounded up so a sub-second one is not
+                    // taken for zero, which disables keep-alive
+                    let secs = keep_alive.as_secs() + u64::from(keep_alive.subsec_nanos() > 0);
+                    server = server.keep_alive(secs as usize);
+                }
+                if let Some(timeout) = settings.client_timeout {
+                    server = server.client_timeout(timeout.as_millis() as u64);
+                }
+                if let Some(timeout) = settings.client_shutdown {
+                    server = server.client_shutdown(timeout.as_millis() as u64);
+                }
+                if let Some(max) = settings.max_connections {
+                    server = server.max_connections(max);
+                }
+                if let Some(max) = settings.max_connection_rate {
+                    server = server.max_connection_rate(max);
+                }
+                if let Some(backlog) = settings.backlog {
+                    server = server.backlog(backlog);
+                }
+                // the drain time of a graceful stop, SIGTERM included
+                if let Some(timeout) = settings.shutdown_timeout {
+                    server = server.shutdown_timeout(timeout.as_secs());
+                }
 
-                Ok(())
+                #tls_config
+                for listener in &settings.listeners {
+                    server = match listener {
+                        spa_server::Listener::Tcp(addr) => server.bind(addr)?,
+                        #bind_https
+                        spa_server::Listener::Unix(path) => {
+                            listener.remove_stale_socket()?;
+                            // unix listeners are only parsed on unix
+                            #[cfg(unix)]
+                            let server = server.bind_uds(path)?;
+                            #[cfg(not(unix))]
+                            let _ = path;
+                            server
+                        }
+                    };
+                }
+                let addrs = server.addrs();
+
+                Ok(spa_server::ServerHandle::new(server.run(), addrs))
             }
         }
 
-        #embed_tokens
+        #(#embed_tokens)*
     })
 }
 
 #[derive(Default)]
 struct Options {
     apis: Vec<Api>,
-    static_files: String,
+    mounts: Vec<Mount>,
     cors: bool,
     identity: Option<Identity>,
+    cache: Cache,
+    filter: Filter,
+    mime: MimeTypes,
+    /// release the files on disk instead of serving them from memory, with
+    /// optional target dir
+    extract: Option<Option<String>>,
+    /// url path of the asset manifest endpoint
+    manifest: Option<String>,
+    fallback: Fallback,
+    config: Option<IndexConfig>,
+    csp: Option<Csp>,
+    error_pages: Option<ErrorPages>,
+    pwa: Option<Pwa>,
+    build_id: Option<BuildId>,
+    tls: Option<Tls>,
+    /// seconds
+    shutdown_timeout: Option<u64>,
+    /// url path the app is served under, root by default
+    base_path: String,
+}
+
+fn option_str(s: &Option<String>) -> TokenStream {
+    match s {
+        Some(s) => quote! { Some(#s) },
+        None => quote! { None },
+    }
+}
+
+#[derive(Default)]
+struct Fallback {
+    /// url prefixes answer json 404 instead of index.html, all the api
+    /// prefixes by default
+    exclude: Option<Vec<String>>,
+    /// variant of spa_server::Matcher, NoExtension by default
+    matcher: Option<Ident>,
+}
+
+impl Fallback {
+    /// fallback(exclude("/api", "/internal"), matcher = "no_extension")
+    fn parse(list: &MetaList) -> Result<Self> {
+        let mut fallback = Fallback::default();
+        for nm in &list.nested {
+            match nm {
+                NestedMeta::Meta(Meta::List(l)) if l.path.is_ident("exclude") => {
+                    let mut exclude = Vec::new();
+                    for p in &l.nested {
+                        match p {
+                            NestedMeta::Lit(lit) => exclude.push(LitWrap { inner: lit }.parse()?),
+                            _ => return Err(Error::new(p.span(), "expect a url prefix string")),
+                        }
+                    }
+                    fallback.exclude = Some(exclude);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("matcher") => {
+                    let matcher = LitWrap { inner: &nv.lit }.parse::<String>()?;
+                    let variant = match matcher.as_str() {
+                        "no_dot" => "NoDot",
+                        "no_extension" => "NoExtension",
+                        "accept_html" => "AcceptHtml",
+                        "all" => "All",
+                        _ => {
+                            return Err(Error::new(
+                                nv.lit.span(),
+                                "matcher must be one of no_dot, no_extension, accept_html, all",
+                            ))
+                        }
+                    };
+                    fallback.matcher = Some(Ident::new(variant, nv.lit.span()));
+                }
+                _ => {
+                    return Err(Error::new(
+                        nm.span(),
+                        "expect exclude(...) or matcher = \"...\" in fallback",
+                    ))
+                }
+            }
+        }
+
+        Ok(fallback)
+    }
+}
+
+#[derive(Default)]
+struct IndexConfig {
+    /// method of the struct returns the config, anything serialize to a json
+    /// object
+    method: Option<Ident>,
+    /// environment variables with this prefix are added to the config
+    env_prefix: Option<String>,
+    /// placeholder in index.html, spa_server::CONFIG_PLACEHOLDER by default
+    placeholder: Option<String>,
+}
+
+impl IndexConfig {
+    /// config(method = "spa_config", env_prefix = "APP_", placeholder = "<!--SPA_CONFIG-->")
+    fn parse(list: &MetaList) -> Result<Self> {
+        let mut config = IndexConfig::default();
+        for nm in &list.nested {
+            match nm {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("method") => {
+                    let method = LitWrap { inner: &nv.lit }.parse::<String>()?;
+                    config.method = Some(Ident::new(&method, nv.lit.span()));
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("env_prefix") => {
+                    config.env_prefix = Some(LitWrap { inner: &nv.lit }.parse()?);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("placeholder") => {
+                    config.placeholder = Some(LitWrap { inner: &nv.lit }.parse()?);
+                }
+                _ => {
+                    return Err(Error::new(
+                        nm.span(),
+                        "expect method, env_prefix or placeholder in config",
+                    ))
+                }
+            }
+        }
+
+        if config.method.is_none() && config.env_prefix.is_none() {
+            return Err(Error::new(
+                list.span(),
+                "config needs a method or an env_prefix",
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+struct Pwa {
+    /// service worker, relative to static_files
+    service_worker: String,
+    /// Service-Worker-Allowed header, relative to the base path
+    scope: Option<String>,
+    /// url path of the precache manifest
+    precache: Option<String>,
+}
+
+impl Pwa {
+    /// pwa(service_worker = "sw.js", scope = "/", precache = "/precache-manifest.json")
+    fn parse(list: &MetaList) -> Result<Self> {
+        let mut service_worker = None;
+        let mut scope = None;
+        let mut precache = None;
+        for nm in &list.nested {
+            match nm {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("service_worker") => {
+                    service_worker = Some(LitWrap { inner: &nv.lit }.parse()?);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("scope") => {
+                    scope = Some(LitWrap { inner: &nv.lit }.parse()?);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("precache") => {
+                    precache = Some(LitWrap { inner: &nv.lit }.parse()?);
+                }
+                _ => {
+                    return Err(Error::new(
+                        nm.span(),
+                        "expect service_worker, scope or precache in pwa",
+                    ))
+                }
+            }
+        }
+
+        Ok(Pwa {
+            service_worker: service_worker
+                .ok_or_else(|| Error::new(list.span(), "must set service_worker of pwa"))?,
+            scope,
+            precache,
+        })
+    }
+}
+
+struct Tls {
+    /// PEM certificate chain, read at runtime
+    cert: String,
+    /// PEM private key, read at runtime
+    key: String,
+    /// reload the files on SIGHUP
+    reload: bool,
+}
+
+impl Tls {
+    /// tls(cert = "/etc/app/cert.pem", key = "/etc/app/key.pem", reload)
+    fn parse(list: &MetaList) -> Result<Self> {
+        // without rustls every listener of `run(port)` would turn https and
+        // fail at startup
+        if !cfg!(feature = "tls") {
+            return Err(Error::new(
+                list.span(),
+                "tls needs the tls feature of spa-server",
+            ));
+        }
+
+        let mut cert = None;
+        let mut key = None;
+        let mut reload = false;
+        for nm in &list.nested {
+            match nm {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("cert") => {
+                    cert = Some(LitWrap { inner: &nv.lit }.parse()?);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("key") => {
+                    key = Some(LitWrap { inner: &nv.lit }.parse()?);
+                }
+                NestedMeta::Meta(Meta::Path(p)) if p.is_ident("reload") => {
+                    reload = true;
+                }
+                _ => return Err(Error::new(nm.span(), "expect cert, key or reload in tls")),
+            }
+        }
+
+        Ok(Tls {
+            cert: cert.ok_or_else(|| Error::new(list.span(), "must set cert of tls"))?,
+            key: key.ok_or_else(|| Error::new(list.span(), "must set key of tls"))?,
+            reload,
+        })
+    }
+}
+
+#[derive(Default)]
+struct ErrorPages {
+    /// page of the 404 responses, relative to static_files
+    not_found: Option<String>,
+    /// page of the 500 responses, relative to static_files
+    server_error: Option<String>,
+}
+
+impl ErrorPages {
+    /// error_pages(not_found = "404.html", server_error = "500.html")
+    fn parse(list: &MetaList) -> Result<Self> {
+        let mut pages = ErrorPages::default();
+        for nm in &list.nested {
+            match nm {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("not_found") => {
+                    pages.not_found = Some(LitWrap { inner: &nv.lit }.parse()?);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("server_error") => {
+                    pages.server_error = Some(LitWrap { inner: &nv.lit }.parse()?);
+                }
+                _ => {
+                    return Err(Error::new(
+                        nm.span(),
+                        "expect not_found or server_error in error_pages",
+                    ))
+                }
+            }
+        }
+
+        Ok(pages)
+    }
+}
+
+#[derive(Default)]
+struct Csp {
+    /// policy with `{nonce}` placeholders, spa_server::DEFAULT_CSP by default
+    policy: Option<String>,
+    /// send Content-Security-Policy-Report-Only instead
+    report_only: bool,
+}
+
+impl Csp {
+    /// csp(policy = "script-src 'self' 'nonce-{nonce}'", report_only)
+    fn parse(list: &MetaList) -> Result<Self> {
+        let mut csp = Csp::default();
+        for nm in &list.nested {
+            match nm {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("policy") => {
+                    csp.policy = Some(LitWrap { inner: &nv.lit }.parse()?);
+                }
+                NestedMeta::Meta(Meta::Path(p)) if p.is_ident("report_only") => {
+                    csp.report_only = true;
+                }
+                _ => {
+                    return Err(Error::new(
+                        nm.span(),
+                        "expect policy = \"...\" or report_only in csp",
+                    ))
+                }
+            }
+        }
+
+        Ok(csp)
+    }
+}
+
+struct BuildId {
+    /// url path of the json endpoint
+    path: String,
+    /// response header of the api calls
+    header: String,
+}
+
+impl Default for BuildId {
+    fn default() -> Self {
+        BuildId {
+            path: "/build-id".to_string(),
+            header: "x-build-id".to_string(),
+        }
+    }
+}
+
+impl BuildId {
+    /// build_id(path = "/build-id", header = "x-build-id")
+    fn parse(list: &MetaList) -> Result<Self> {
+        let mut build_id = BuildId::default();
+        for nm in &list.nested {
+            match nm {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("path") => {
+                    build_id.path = LitWrap { inner: &nv.lit }.parse()?;
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("header") => {
+                    let header: String = LitWrap { inner: &nv.lit }.parse()?;
+                    if header.is_empty()
+                        || !header
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                    {
+                        return Err(Error::new(
+                            nv.lit.span(),
+                            format!("invalid header name {}", header),
+                        ));
+                    }
+                    build_id.header = header;
+                }
+                _ => return Err(Error::new(nm.span(), "expect path or header in build_id")),
+            }
+        }
+
+        Ok(build_id)
+    }
 }
 
 #[derive(Default)]
@@ -261,6 +1242,13 @@ struct Api {
     prefix: Option<String>,
 }
 
+struct Mount {
+    dir: String,
+    at: String,
+    /// index page of a SPA mount, None for plain assets
+    index: Option<String>,
+}
+
 #[allow(dead_code)]
 #[derive(Default)]
 struct Identity {
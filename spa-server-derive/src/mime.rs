@@ -0,0 +1,99 @@
+use crate::utils::LitWrap;
+use std::{collections::HashMap, path::Path};
+use syn::{spanned::Spanned, Error, Meta, MetaList, NestedMeta, Result};
+
+/// types mime_guess gets wrong or doesn't know
+const DEFAULTS: &[(&str, &str)] = &[
+    ("wasm", "application/wasm"),
+    ("webmanifest", "application/manifest+json"),
+    ("mjs", "text/javascript"),
+];
+
+/// mime type of the embedded files, resolved from the file extension at compile
+/// time. the mapping of an extension can be overridden with
+/// `mime(ext = "wasm", type = "application/wasm")`
+#[derive(Default)]
+pub(crate) struct MimeTypes {
+    overrides: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    pub fn parse_override(&mut self, list: &MetaList) -> Result<()> {
+        let mut ext = None;
+        let mut ty = None;
+        for nm in &list.nested {
+            match nm {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("ext") => {
+                    ext = Some(LitWrap { inner: &nv.lit }.parse::<String>()?);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("type") => {
+                    ty = Some(LitWrap { inner: &nv.lit }.parse::<String>()?);
+                }
+                _ => {
+                    return Err(Error::new(
+                        nm.span(),
+                        "expect ext = \"...\" or type = \"...\"",
+                    ))
+                }
+            }
+        }
+
+        match (ext, ty) {
+            (Some(ext), Some(ty)) => {
+                let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+                self.overrides.insert(ext, ty);
+                Ok(())
+            }
+            _ => Err(Error::new(list.span(), "mime must set both ext and type")),
+        }
+    }
+
+    /// extension to mime type pairs checked at runtime before the usual guess,
+    /// for the files without compile time metadata
+    pub fn table(&self) -> Vec<(String, String)> {
+        let mut table = self
+            .overrides
+            .iter()
+            .map(|(ext, mime)| (ext.clone(), mime.clone()))
+            .collect::<Vec<_>>();
+        for (ext, mime) in DEFAULTS {
+            if !self.overrides.contains_key(*ext) {
+                table.push((ext.to_string(), mime.to_string()));
+            }
+        }
+
+        table
+    }
+
+    /// mime type and charset of the file
+    pub fn guess(&self, rel_path: &str) -> (String, Option<&'static str>) {
+        let ext = Path::new(rel_path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        let mime = match self.overrides.get(&ext) {
+            Some(mime) => mime.clone(),
+            None => match DEFAULTS.iter().find(|(e, _)| *e == ext) {
+                Some((_, mime)) => mime.to_string(),
+                None => mime_guess::from_ext(&ext)
+                    .first_or_octet_stream()
+                    .essence_str()
+                    .to_string(),
+            },
+        };
+
+        let charset = if mime.starts_with("text/")
+            || mime.ends_with("javascript")
+            || mime.ends_with("json")
+            || mime.ends_with("xml")
+        {
+            Some("utf-8")
+        } else {
+            None
+        };
+
+        (mime, charset)
+    }
+}
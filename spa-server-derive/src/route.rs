@@ -297,7 +297,11 @@ impl ToTokens for Route {
                         .name(#resource_name)
                         #method_guards
                         #(.guard(spa_server::guard::fn_guard(#guards)))*
+                        // innermost, a no-op without the build_id option
+                        .wrap_fn(spa_server::build_id_header)
                         #(.wrap(#wrappers))*
+                        // outermost, the errors of the wrappers are the api's too
+                        .wrap_fn(spa_server::api_marker)
                         .#resource_type(#name);
 
                     spa_server::re_export::dev::HttpServiceFactory::register(__resource, __config)
@@ -1,4 +1,7 @@
+use crate::filter::Filter;
 use proc_macro2::Span;
+use regex::Regex;
+use sha2::{Digest, Sha256};
 use std::{
     fmt::Display,
     fs::canonicalize,
@@ -12,7 +15,10 @@ pub struct FileEntry {
     pub full_canonical_path: String,
 }
 
-pub fn get_files(folder_path: impl Into<String>) -> impl Iterator<Item = FileEntry> {
+pub fn get_files<'a>(
+    folder_path: impl Into<String>,
+    filter: &'a Filter,
+) -> impl Iterator<Item = FileEntry> + 'a {
     let folder_path = folder_path.into();
     walkdir::WalkDir::new(&folder_path)
         .follow_links(true)
@@ -35,15 +41,75 @@ pub fn get_files(folder_path: impl Into<String>) -> impl Iterator<Item = FileEnt
                 full_canonical_path,
             }
         })
+        .filter(move |e| filter.matches(&e.rel_path))
 }
 
-fn path_to_str<P: AsRef<Path>>(p: P) -> String {
+pub(crate) fn path_to_str<P: AsRef<Path>>(p: P) -> String {
     p.as_ref()
         .to_str()
         .expect("Path does not have a string representation")
         .to_owned()
 }
 
+/// compile a file pattern, relative to the static files folder. the pattern is
+/// a glob unless prefixed with `regex:`
+pub(crate) fn pattern(s: &str) -> std::result::Result<Regex, regex::Error> {
+    match s.strip_prefix("regex:") {
+        Some(re) => Regex::new(re),
+        None => Regex::new(&glob_to_regex(s)),
+    }
+}
+
+/// translate glob to an anchored regex. supports `*`, `**`, `?`, `{a,b}` and
+/// `[hash]`, which matches the fingerprint (6 or more hex digits) that bundlers
+/// put in the file names, e.g. `main.3f2a1c.js`
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut braces = 0;
+    let mut rest = glob;
+    while let Some(c) = rest.chars().next() {
+        if let Some(r) = rest.strip_prefix("[hash]") {
+            re.push_str("[0-9a-fA-F]{6,}");
+            rest = r;
+            continue;
+        } else if let Some(r) = rest.strip_prefix("**/") {
+            re.push_str("(?:.*/)?");
+            rest = r;
+            continue;
+        } else if let Some(r) = rest.strip_prefix("**") {
+            re.push_str(".*");
+            rest = r;
+            continue;
+        }
+
+        match c {
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '{' => {
+                braces += 1;
+                re.push_str("(?:");
+            }
+            '}' if braces > 0 => {
+                braces -= 1;
+                re.push(')');
+            }
+            ',' if braces > 0 => re.push('|'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    re.push('$');
+    re
+}
+
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
 pub(crate) struct LitWrap<'a> {
     pub inner: &'a Lit,
 }
@@ -109,3 +175,61 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern_str: &str, path: &str) -> bool {
+        pattern(pattern_str).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn double_star_matches_root() {
+        assert!(matches("**/*.map", "main.js.map"));
+        assert!(matches("**/*.map", "js/vendor/main.js.map"));
+        assert!(!matches("**/*.map", "main.js"));
+        assert!(matches("assets/**", "assets/img/logo.png"));
+    }
+
+    #[test]
+    fn star_stays_in_segment() {
+        assert!(matches("*.js", "main.js"));
+        assert!(!matches("*.js", "js/main.js"));
+        assert!(matches("js/?.js", "js/a.js"));
+        assert!(!matches("?.js", "/.js"));
+    }
+
+    #[test]
+    fn braces() {
+        assert!(matches("*.{css,js}", "main.css"));
+        assert!(matches("*.{css,js}", "main.js"));
+        assert!(!matches("*.{css,js}", "main.json"));
+        // a comma out of braces is a literal
+        assert!(matches("a,b.txt", "a,b.txt"));
+    }
+
+    #[test]
+    fn literal_dot() {
+        assert!(matches("index.html", "index.html"));
+        assert!(!matches("index.html", "indexxhtml"));
+        assert!(!matches("index.html", "index.html.bak"));
+    }
+
+    #[test]
+    fn hash() {
+        assert!(matches("**/*.[hash].*", "main.3f2a1c.js"));
+        assert!(matches("**/*.[hash].*", "assets/app.0123456789abcdef.css"));
+        assert!(!matches("**/*.[hash].*", "main.js"));
+        // fewer than 6 hex digits is no fingerprint
+        assert!(!matches("**/*.[hash].*", "main.3f2a.js"));
+        assert!(!matches("**/*.[hash].*", "main.zzzzzz.js"));
+    }
+
+    #[test]
+    fn regex_prefix() {
+        assert!(matches(r"regex:^js/.*\.js$", "js/a/b.js"));
+        assert!(!matches(r"regex:^js/.*\.js$", "css/a.css"));
+        assert!(pattern("regex:(").is_err());
+    }
+}
@@ -0,0 +1,538 @@
+use crate::{
+    fallback::{fallback_of, json_not_found},
+    pwa::pwa_of,
+    template::{render_index, template_of},
+    Embed, Encoding, Metadata,
+};
+use actix_files::NamedFile;
+use actix_web::{
+    guard,
+    http::{
+        header::{
+            HeaderValue, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, ETAG,
+            IF_NONE_MATCH, LOCATION, VARY,
+        },
+        StatusCode,
+    },
+    web,
+    web::Bytes,
+    HttpRequest, HttpResponse, Resource, Responder, Route,
+};
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+/// the default SPA entry page, served for the mount path and all the client
+/// side routes under it
+pub(crate) const INDEX_FILE: &str = "index.html";
+
+/// service serves a SPA under the url path `at`: the embedded files, and the
+/// `index` page for the client side routes according to the fallback rules
+#[doc(hidden)]
+pub fn spa_service<T>(at: &str, index: &str) -> Resource
+where
+    T: Embed + 'static,
+{
+    web::resource(mount_pattern(at))
+        .app_data(web::Data::new(SpaIndex(index.to_string())))
+        .route(get_or_head().to(spa::<T>))
+}
+
+/// url path all the services are mounted under, with a leading slash and
+/// without trailing one, empty for the root
+#[doc(hidden)]
+pub fn base_path(path: &str) -> String {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", path)
+    }
+}
+
+/// service redirects the base path without trailing slash, it's registered
+/// in the scope of the base path and never matches for the root
+#[doc(hidden)]
+pub fn base_redirect() -> Resource {
+    web::resource("").route(get_or_head().to(|req: HttpRequest| async move {
+        slash_redirect(&req).unwrap_or_else(|| HttpResponse::NotFound().finish())
+    }))
+}
+
+/// service serves all the embedded files straight from memory, under the url
+/// path `at`
+#[doc(hidden)]
+pub fn asset_service<T>(at: &str) -> Resource
+where
+    T: Embed + 'static,
+{
+    web::resource(mount_pattern(at)).route(get_or_head().to(asset::<T>))
+}
+
+/// service serves the files of an extracted asset folder under the url path
+/// `at`, a SPA mount also falls back to its `index` page like `spa_service`
+#[doc(hidden)]
+pub fn extracted_service<T>(at: &str, dir: PathBuf, index: Option<&str>) -> Resource
+where
+    T: Embed + 'static,
+{
+    let index = index.map(|i| i.to_string());
+    web::resource(mount_pattern(at))
+        .app_data(web::Data::new(ExtractedDir {
+            dir,
+            index,
+            metadata: T::metadata,
+            content_type: T::content_type,
+        }))
+        .route(get_or_head().to(extracted))
+}
+
+struct SpaIndex(String);
+
+struct ExtractedDir {
+    dir: PathBuf,
+    /// index page of a SPA mount, None for plain assets
+    index: Option<String>,
+    /// compile time metadata of the embedded files, the same as on disk
+    metadata: fn(&str) -> Option<Metadata>,
+    content_type: fn(&str) -> String,
+}
+
+fn get_or_head() -> Route {
+    web::route().guard(guard::Any(guard::Get()).or(guard::Head()))
+}
+
+fn mount_pattern(at: &str) -> String {
+    let at = at.trim_end_matches('/');
+    if at.is_empty() {
+        "/{tail:.*}".to_string()
+    } else {
+        // matches `at` itself, but not `at` followed by anything else than `/`
+        format!("{}{{tail:(/.*)?}}", at)
+    }
+}
+
+/// file path of the matched url tail, directories map to their `index` page
+fn file_path<'a>(req: &'a HttpRequest, index: &str) -> Cow<'a, str> {
+    let path = req.match_info().query("tail").trim_start_matches('/');
+    if path.is_empty() || path.ends_with('/') {
+        Cow::from(format!("{}{}", path, index))
+    } else {
+        Cow::from(path)
+    }
+}
+
+/// mount path without trailing slash needs a redirect, relative links need it
+fn slash_redirect(req: &HttpRequest) -> Option<HttpResponse> {
+    if !req.match_info().query("tail").is_empty() || req.path().ends_with('/') {
+        return None;
+    }
+
+    let mut location = format!("{}/", req.path());
+    if !req.query_string().is_empty() {
+        location = format!("{}?{}", location, req.query_string());
+    }
+
+    Some(
+        HttpResponse::MovedPermanently()
+            .insert_header((LOCATION, location))
+            .finish(),
+    )
+}
+
+async fn spa<T: Embed>(req: HttpRequest, index: web::Data<SpaIndex>) -> HttpResponse {
+    if let Some(redirect) = slash_redirect(&req) {
+        return redirect;
+    }
+
+    let index = index.0.as_str();
+    let path = file_path(&req, index);
+    let found = if path == index {
+        respond_page::<T>(&req, index)
+    } else {
+        respond::<T>(&req, &path)
+    };
+    if let Some(resp) = found {
+        return resp;
+    }
+
+    let fallback = fallback_of(&req);
+    if fallback.is_excluded(req.path()) {
+        return json_not_found(&req);
+    }
+
+    // a pre-rendered page of the route goes before the SPA shell
+    if let Some(page) = T::page(&path) {
+        if let Some(resp) = respond_page::<T>(&req, &page) {
+            return resp;
+        }
+    }
+
+    if fallback.matches(&req, &path) {
+        if let Some(resp) = respond_page::<T>(&req, index) {
+            return resp;
+        }
+    }
+
+    HttpResponse::NotFound().finish()
+}
+
+/// an html page of a SPA, the index or a pre-rendered one, rendered through
+/// the template if any
+fn respond_page<T: Embed>(req: &HttpRequest, page: &str) -> Option<HttpResponse> {
+    let html = T::get(page)?;
+    let cache_control = T::metadata(page).and_then(|m| m.cache_control);
+    render_index(req, &html, cache_control).or_else(|| respond::<T>(req, page))
+}
+
+async fn asset<T: Embed>(req: HttpRequest) -> HttpResponse {
+    if let Some(redirect) = slash_redirect(&req) {
+        return redirect;
+    }
+
+    respond::<T>(&req, &file_path(&req, INDEX_FILE))
+        .unwrap_or_else(|| HttpResponse::NotFound().finish())
+}
+
+async fn extracted(req: HttpRequest, extracted: web::Data<ExtractedDir>) -> HttpResponse {
+    serve_extracted(&req, &extracted)
+        .unwrap_or_else(|e| HttpResponse::build(e.as_response_error().status_code()).finish())
+}
+
+fn serve_extracted(req: &HttpRequest, extracted: &ExtractedDir) -> actix_web::Result<HttpResponse> {
+    if let Some(redirect) = slash_redirect(req) {
+        return Ok(redirect);
+    }
+
+    let index = extracted.index.as_deref();
+    let path = file_path(req, index.unwrap_or(INDEX_FILE));
+    let rel = Path::new(path.as_ref());
+    let normal = rel.components().all(|c| matches!(c, Component::Normal(_)));
+    if normal && extracted.dir.join(rel).is_file() {
+        if index == Some(path.as_ref()) {
+            return extracted_page(req, extracted, &path);
+        }
+
+        return extracted_file(req, extracted, &path);
+    }
+
+    if let Some(index) = index {
+        let fallback = fallback_of(req);
+        if fallback.is_excluded(req.path()) {
+            return Ok(json_not_found(req));
+        }
+
+        if normal {
+            let pages = [format!("{}.html", path), format!("{}/index.html", path)];
+            if let Some(page) = pages.iter().find(|page| extracted.dir.join(page).is_file()) {
+                return extracted_page(req, extracted, page);
+            }
+        }
+
+        if fallback.matches(req, &path) {
+            return extracted_page(req, extracted, index);
+        }
+    }
+
+    Ok(HttpResponse::NotFound().finish())
+}
+
+/// an html page of an extracted SPA, rendered through the template if any
+fn extracted_page(
+    req: &HttpRequest,
+    extracted: &ExtractedDir,
+    page: &str,
+) -> actix_web::Result<HttpResponse> {
+    if template_of(req).is_some() {
+        let cache_control = (extracted.metadata)(page).and_then(|m| m.cache_control);
+        let html = fs::read(extracted.dir.join(page))?;
+        if let Some(resp) = render_index(req, &html, cache_control) {
+            return Ok(resp);
+        }
+    }
+
+    extracted_file(req, extracted, page)
+}
+
+/// a file of the extracted folder, with the Cache-Control of the `cache(...)`
+/// rules and the strong entity tag of the embedded file, like `respond`
+fn extracted_file(
+    req: &HttpRequest,
+    extracted: &ExtractedDir,
+    path: &str,
+) -> actix_web::Result<HttpResponse> {
+    let metadata = (extracted.metadata)(path);
+    let etag = metadata.map(|m| etag(m.hash, None));
+    let mut resp = match &etag {
+        Some(etag) if none_match(req, etag) => HttpResponse::NotModified().finish(),
+        _ => {
+            let mut resp = NamedFile::open(extracted.dir.join(path))?
+                .use_etag(etag.is_none())
+                .respond_to(req);
+            // the guess of NamedFile knows nothing of the mime(...) table
+            if resp.headers().contains_key(CONTENT_TYPE) {
+                if let Ok(value) = HeaderValue::from_str(&(extracted.content_type)(path)) {
+                    resp.headers_mut().insert(CONTENT_TYPE, value);
+                }
+            }
+            resp
+        }
+    };
+
+    if let Some(cache_control) = metadata.and_then(|m| m.cache_control) {
+        resp.headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    }
+    if let Some(etag) = etag.and_then(|etag| HeaderValue::from_str(&etag).ok()) {
+        resp.headers_mut().insert(ETAG, etag);
+    }
+    if let Some(pwa) = pwa_of(req) {
+        pwa.apply(req, path, &mut resp);
+    }
+
+    Ok(resp)
+}
+
+/// the full body is always attached, for HEAD request the h1 codec only sends
+/// the headers (including Content-Length) and drops the payload
+fn respond<T: Embed>(req: &HttpRequest, path: &str) -> Option<HttpResponse> {
+    let body = T::get(path)?;
+
+    let variants = [Encoding::Brotli, Encoding::Gzip]
+        .iter()
+        .filter_map(|e| T::get_encoded(path, *e).map(|data| (*e, data)))
+        .collect::<Vec<_>>();
+    let encoded = negotiate(req, &variants);
+
+    let mut resp = HttpResponse::Ok();
+    if !variants.is_empty() {
+        resp.insert_header((VARY, ACCEPT_ENCODING.as_str()));
+    }
+
+    let metadata = T::metadata(path);
+    if let Some(metadata) = metadata {
+        if let Some(cache_control) = metadata.cache_control {
+            resp.insert_header((CACHE_CONTROL, cache_control));
+        }
+
+        let etag = etag(metadata.hash, encoded.map(|(e, _)| e));
+
+        if none_match(req, &etag) {
+            let mut resp = resp
+                .status(StatusCode::NOT_MODIFIED)
+                .insert_header((ETAG, etag))
+                .finish();
+            // a revalidated service worker keeps its no-cache and scope
+            if let Some(pwa) = pwa_of(req) {
+                pwa.apply(req, path, &mut resp);
+            }
+            return Some(resp);
+        }
+
+        resp.insert_header((ETAG, etag));
+    }
+
+    match metadata {
+        Some(metadata) => resp.content_type(metadata.content_type()),
+        // files read from disk in debug build have no metadata
+        None => resp.content_type(T::content_type(path)),
+    };
+
+    let mut resp = match encoded {
+        Some((encoding, data)) => resp
+            .insert_header((CONTENT_ENCODING, encoding.as_str()))
+            .body(Bytes::from_static(data)),
+        None => resp.body(to_bytes(body)),
+    };
+
+    if let Some(pwa) = pwa_of(req) {
+        pwa.apply(req, path, &mut resp);
+    }
+
+    Some(resp)
+}
+
+/// strong entity tag of the file content, every representation needs its own
+/// so the encoding goes into it
+fn etag(hash: &str, encoding: Option<Encoding>) -> String {
+    match encoding {
+        Some(encoding) => format!("\"{}-{}\"", hash, encoding.as_str()),
+        None => format!("\"{}\"", hash),
+    }
+}
+
+/// whether `If-None-Match` matches the current entity tag, the comparison is
+/// weak as required for this header
+fn none_match(req: &HttpRequest, etag: &str) -> bool {
+    let header = match req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(header) => header,
+        None => return false,
+    };
+
+    header
+        .split(',')
+        .map(|t| t.trim())
+        .any(|t| t == "*" || t.strip_prefix("W/").unwrap_or(t) == etag)
+}
+
+/// pick the precompressed variant with the highest quality value in
+/// `Accept-Encoding`, brotli wins the tie since it's listed first
+fn negotiate(
+    req: &HttpRequest,
+    variants: &[(Encoding, &'static [u8])],
+) -> Option<(Encoding, &'static [u8])> {
+    let accept = req
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())?;
+
+    let mut best: Option<(f32, Encoding, &'static [u8])> = None;
+    for (encoding, data) in variants {
+        let q = quality(accept, encoding.as_str());
+        if q > 0.0 && best.map(|(bq, _, _)| q > bq).unwrap_or(true) {
+            best = Some((q, *encoding, *data));
+        }
+    }
+
+    best.map(|(_, encoding, data)| (encoding, data))
+}
+
+/// quality value of `coding` in an `Accept-Encoding` header, 0 means not acceptable
+fn quality(accept: &str, coding: &str) -> f32 {
+    let mut wildcard = 0.0;
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .next()
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if name.eq_ignore_ascii_case(coding) {
+            return q;
+        } else if name == "*" {
+            wildcard = q;
+        }
+    }
+
+    wildcard
+}
+
+pub(crate) fn to_bytes(data: Cow<'static, [u8]>) -> Bytes {
+    match data {
+        Cow::Borrowed(b) => Bytes::from_static(b),
+        Cow::Owned(v) => Bytes::from(v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    const GZ: &[u8] = b"gz";
+    const BR: &[u8] = b"br";
+    const BOTH: &[(Encoding, &[u8])] = &[(Encoding::Brotli, BR), (Encoding::Gzip, GZ)];
+
+    fn accept(accept_encoding: &str) -> HttpRequest {
+        TestRequest::default()
+            .insert_header((ACCEPT_ENCODING, accept_encoding))
+            .to_http_request()
+    }
+
+    fn negotiated(
+        accept_encoding: &str,
+        variants: &[(Encoding, &'static [u8])],
+    ) -> Option<Encoding> {
+        negotiate(&accept(accept_encoding), variants).map(|(e, _)| e)
+    }
+
+    #[test]
+    fn quality_values() {
+        assert_eq!(quality("gzip, br;q=0.5", "gzip"), 1.0);
+        assert_eq!(quality("gzip, br;q=0.5", "br"), 0.5);
+        assert_eq!(quality("gzip;q=0", "gzip"), 0.0);
+        assert_eq!(quality("gzip", "br"), 0.0);
+    }
+
+    #[test]
+    fn q_zero_rejected() {
+        assert_eq!(negotiated("br;q=0, gzip", BOTH), Some(Encoding::Gzip));
+        assert_eq!(negotiated("br;q=0, gzip;q=0", BOTH), None);
+    }
+
+    #[test]
+    fn wildcard() {
+        assert_eq!(quality("*;q=0.3", "gzip"), 0.3);
+        assert_eq!(negotiated("*", BOTH), Some(Encoding::Brotli));
+        // an explicit token wins over the wildcard
+        assert_eq!(negotiated("*, br;q=0", BOTH), Some(Encoding::Gzip));
+        assert_eq!(negotiated("identity, *;q=0", BOTH), None);
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert_eq!(quality("GZip", "gzip"), 1.0);
+        assert_eq!(negotiated("BR", BOTH), Some(Encoding::Brotli));
+    }
+
+    #[test]
+    fn brotli_wins_tie() {
+        assert_eq!(
+            negotiated("gzip, deflate, br", BOTH),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(negotiated("gzip, br;q=0.9", BOTH), Some(Encoding::Gzip));
+        assert_eq!(
+            negotiated("br", &[(Encoding::Gzip, GZ)]),
+            None,
+            "no variant acceptable"
+        );
+    }
+
+    fn if_none_match(header: &str, etag: &str) -> bool {
+        let req = TestRequest::default()
+            .insert_header((IF_NONE_MATCH, header))
+            .to_http_request();
+        none_match(&req, etag)
+    }
+
+    #[test]
+    fn etag_per_encoding() {
+        assert_eq!(etag("abc", None), "\"abc\"");
+        assert_eq!(etag("abc", Some(Encoding::Gzip)), "\"abc-gzip\"");
+        assert_eq!(etag("abc", Some(Encoding::Brotli)), "\"abc-br\"");
+    }
+
+    #[test]
+    fn none_match_weak() {
+        let gz = etag("abc", Some(Encoding::Gzip));
+        assert!(if_none_match("\"abc-gzip\"", &gz));
+        assert!(if_none_match("W/\"abc-gzip\"", &gz));
+        // the validator of another representation doesn't match
+        assert!(!if_none_match("\"abc\"", &gz));
+        assert!(!if_none_match("\"abc-br\"", &gz));
+    }
+
+    #[test]
+    fn none_match_list_and_star() {
+        let raw = etag("abc", None);
+        assert!(if_none_match("\"x\", W/\"abc\" ,\"y\"", &raw));
+        assert!(!if_none_match("\"x\", \"y\"", &raw));
+        assert!(if_none_match("*", &raw));
+        assert!(!none_match(&TestRequest::default().to_http_request(), &raw));
+    }
+
+    #[test]
+    fn no_accept_encoding() {
+        let req = TestRequest::default().to_http_request();
+        assert!(negotiate(&req, BOTH).is_none());
+    }
+}
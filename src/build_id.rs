@@ -0,0 +1,83 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue, CACHE_CONTROL},
+    web, Error, HttpRequest, HttpResponse, Resource,
+};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::Serialize;
+use std::future::Future;
+
+/// header carrying the build id on the api responses by default
+pub const BUILD_ID_HEADER: &str = "x-build-id";
+
+/// identifier of the embedded UI build, so a long-lived tab can tell the
+/// server runs a newer one than it has loaded
+#[doc(hidden)]
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildId {
+    build_id: String,
+    #[serde(skip)]
+    header: Option<(HeaderName, HeaderValue)>,
+}
+
+impl BuildId {
+    /// `build_hash` of the embedded files. when they are read from disk there
+    /// is none, a random id is drawn instead so every restart of the dev
+    /// server counts as a new build
+    pub fn new(build_hash: Option<&str>, header: &str) -> Self {
+        let build_id = match build_hash {
+            Some(hash) => hash.to_string(),
+            None => thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(16)
+                .map(char::from)
+                .collect(),
+        };
+
+        let header = HeaderName::from_bytes(header.as_bytes())
+            .ok()
+            .zip(HeaderValue::from_str(&build_id).ok());
+        BuildId { build_id, header }
+    }
+}
+
+fn build_id_of(req: &HttpRequest) -> Option<&BuildId> {
+    req.app_data::<web::Data<BuildId>>().map(|b| b.get_ref())
+}
+
+/// service answers `{"build_id": "..."}`, never cached
+#[doc(hidden)]
+pub fn build_id_service(path: &str) -> Resource {
+    web::resource(path).route(web::get().to(|req: HttpRequest| async move {
+        match build_id_of(&req) {
+            Some(build_id) => HttpResponse::Ok()
+                .insert_header((CACHE_CONTROL, "no-store"))
+                .json(build_id),
+            None => HttpResponse::NotFound().finish(),
+        }
+    }))
+}
+
+/// `wrap_fn` middleware sets the build id header on the api responses, it
+/// wraps every resource of the route macros, so each api gets it whatever
+/// its prefix and the fallback excludes
+#[doc(hidden)]
+pub fn build_id_header<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let header = build_id_of(req.request()).and_then(|b| b.header.clone());
+
+    let fut = srv.call(req);
+    async move {
+        let mut resp = fut.await?;
+        if let Some((name, value)) = header {
+            resp.headers_mut().insert(name, value);
+        }
+
+        Ok(resp)
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{dev_proxy_from_env, Listener, ServerHandle, TlsConfig};
+use std::{error::Error, time::Duration};
+
+/// shortest key the identity cookie can be signed with
+pub(crate) const MIN_IDENTITY_KEY: usize = 32;
+
+/// settings the server is built with, see `ServerBuilder`
+#[doc(hidden)]
+#[derive(Debug, Clone, Default)]
+pub struct ServerSettings {
+    pub listeners: Vec<Listener>,
+    pub tls: Option<TlsConfig>,
+    pub base_path: String,
+    pub dev_proxy: Option<u16>,
+    /// empty for any origin
+    pub cors_origins: Vec<String>,
+    pub identity_name: Option<String>,
+    /// minutes
+    pub identity_max_age: Option<i64>,
+    pub identity_secure: Option<bool>,
+    pub identity_key: Option<Vec<u8>>,
+    pub workers: Option<usize>,
+    pub keep_alive: Option<Duration>,
+    pub client_timeout: Option<Duration>,
+    pub client_shutdown: Option<Duration>,
+    pub max_connections: Option<usize>,
+    pub max_connection_rate: Option<usize>,
+    pub backlog: Option<u32>,
+    pub payload_limit: Option<usize>,
+    pub shutdown_timeout: Option<Duration>,
+}
+
+/// implemented by `SPAServer`, builds the `HttpServer` out of the settings
+#[doc(hidden)]
+pub trait Serve: Sized {
+    fn serve(self, settings: ServerSettings) -> Result<ServerHandle, Box<dyn Error>>;
+}
+
+/// runtime tuning of the server returned by `builder()` generated by
+/// `SPAServer`, the settings left alone keep the actix-web defaults
+/// ```
+/// Server { data: String::new() }
+///     .builder()
+///     .listen("tcp://127.0.0.1:8080")
+///     .workers(4)
+///     .keep_alive(Duration::from_secs(75))
+///     .payload_limit(16 * 1024 * 1024)
+///     .run()
+///     .await?;
+/// ```
+pub struct ServerBuilder<T> {
+    server: T,
+    /// `host:port`, https when there is a tls certificate
+    addrs: Vec<String>,
+    listeners: Vec<String>,
+    settings: ServerSettings,
+}
+
+impl<T: Serve> ServerBuilder<T> {
+    #[doc(hidden)]
+    pub fn new(
+        server: T,
+        base_path: &str,
+        tls: Option<TlsConfig>,
+        shutdown_timeout: Option<Duration>,
+    ) -> Self {
+        ServerBuilder {
+            server,
+            addrs: Vec::new(),
+            listeners: Vec::new(),
+            settings: ServerSettings {
+                tls,
+                base_path: base_path.to_string(),
+                dev_proxy: dev_proxy_from_env(),
+                shutdown_timeout,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// listen on `0.0.0.0:port`, https when there is a tls certificate
+    pub fn port(self, port: u16) -> Self {
+        self.bind("0.0.0.0", port)
+    }
+
+    /// listen on `host:port`, the host is an ip address or a name, https when
+    /// there is a tls certificate
+    pub fn bind(mut self, host: &str, port: u16) -> Self {
+        if host.contains(':') && !host.starts_with('[') {
+            self.addrs.push(format!("[{}]:{}", host, port));
+        } else {
+            self.addrs.push(format!("{}:{}", host, port));
+        }
+        self
+    }
+
+    /// listen on `listener`, e.g. `tcp://127.0.0.1:8080`, `tcp://[::]:8080`,
+    /// `https://[::]:8443` or `unix:///run/app.sock`
+    pub fn listen(mut self, listener: impl Into<String>) -> Self {
+        self.listeners.push(listener.into());
+        self
+    }
+
+    /// certificate of the https listeners, instead of the one set in the
+    /// attribute
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.settings.tls = Some(tls);
+        self
+    }
+
+    /// serve everything under `base_path` instead of the one set in the
+    /// attribute
+    pub fn base_path(mut self, base_path: &str) -> Self {
+        self.settings.base_path = base_path.to_string();
+        self
+    }
+
+    /// forward the requests out of the apis to the frontend dev server
+    /// listening at `127.0.0.1:port`
+    pub fn dev_proxy(mut self, port: u16) -> Self {
+        self.settings.dev_proxy = Some(port);
+        self
+    }
+
+    /// allow cross origin requests from `origin` only, instead of any origin.
+    /// needs the `cors` option
+    pub fn cors_origin(mut self, origin: &str) -> Self {
+        self.settings.cors_origins.push(origin.to_string());
+        self
+    }
+
+    /// name of the identity cookie, needs the `identity` option
+    pub fn identity_name(mut self, name: &str) -> Self {
+        self.settings.identity_name = Some(name.to_string());
+        self
+    }
+
+    /// minutes the identity cookie lasts
+    pub fn identity_max_age(mut self, minutes: i64) -> Self {
+        self.settings.identity_max_age = Some(minutes);
+        self
+    }
+
+    /// send the identity cookie over https only
+    pub fn identity_secure(mut self, secure: bool) -> Self {
+        self.settings.identity_secure = Some(secure);
+        self
+    }
+
+    /// key signing the identity cookie, at least 32 bytes or `start` fails
+    pub fn identity_key(mut self, key: &[u8]) -> Self {
+        self.settings.identity_key = Some(key.to_vec());
+        self
+    }
+
+    /// number of worker threads, the number of CPUs by default
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.settings.workers = Some(workers);
+        self
+    }
+
+    /// how long an idle keep-alive connection stays open, in whole seconds
+    /// rounded up, zero disables keep-alive
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.settings.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// time a client has to send the request head
+    pub fn client_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.client_timeout = Some(timeout);
+        self
+    }
+
+    /// time a client has to acknowledge the connection shutdown
+    pub fn client_shutdown(mut self, timeout: Duration) -> Self {
+        self.settings.client_shutdown = Some(timeout);
+        self
+    }
+
+    /// concurrent connections of each worker
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.settings.max_connections = Some(max);
+        self
+    }
+
+    /// concurrent TLS handshakes of each worker
+    pub fn max_connection_rate(mut self, max: usize) -> Self {
+        self.settings.max_connection_rate = Some(max);
+        self
+    }
+
+    /// pending connections queued by the listeners
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.settings.backlog = Some(backlog);
+        self
+    }
+
+    /// size limit of the request bodies read by the `Bytes`, `String` and
+    /// `Json` extractors of the apis
+    pub fn payload_limit(mut self, limit: usize) -> Self {
+        self.settings.payload_limit = Some(limit);
+        self
+    }
+
+    /// how long a graceful stop, SIGTERM included, waits for the in-flight
+    /// requests, instead of the one set in the attribute
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// start the server in the background, see `ServerHandle`. must be called
+    /// inside the actix runtime, e.g. in a `#[spa_server::main]` function
+    pub fn start(self) -> Result<ServerHandle, Box<dyn Error>> {
+        let ServerBuilder {
+            server,
+            addrs,
+            listeners,
+            mut settings,
+        } = self;
+
+        let https = settings.tls.is_some();
+        for addr in addrs {
+            settings.listeners.push(if https {
+                Listener::Https(addr)
+            } else {
+                Listener::Tcp(addr)
+            });
+        }
+        for listener in listeners {
+            settings.listeners.push(listener.parse()?);
+        }
+        if settings.listeners.is_empty() {
+            return Err("no listener to run".into());
+        }
+        // checked here, a shorter key panics in every worker instead
+        if let Some(key) = &settings.identity_key {
+            if key.len() < MIN_IDENTITY_KEY {
+                return Err(format!(
+                    "identity key of {} bytes, expect at least {}",
+                    key.len(),
+                    MIN_IDENTITY_KEY
+                )
+                .into());
+            }
+        }
+
+        server.serve(settings)
+    }
+
+    /// start the server and wait until it stops
+    pub async fn run(self) -> Result<(), Box<dyn Error>> {
+        self.start()?.await?;
+        Ok(())
+    }
+}
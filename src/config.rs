@@ -0,0 +1,508 @@
+use crate::{builder::MIN_IDENTITY_KEY, Serve, ServerBuilder};
+use log::{info, LevelFilter};
+use serde_json::{Map, Value};
+use std::{
+    env,
+    error::Error,
+    fmt::{self, Display},
+    fs,
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+};
+
+/// env var with the path of the TOML or JSON config file
+pub const CONFIG_FILE_ENV: &str = "SPA_CONFIG";
+
+/// prefix of the env vars overriding the config keys, e.g. `SPA_PORT`
+const ENV_PREFIX: &str = "SPA_";
+
+/// the config keys, only their `SPA_*` env vars are read so the other ones,
+/// e.g. of the app, are left alone
+const KEYS: &[&str] = &[
+    "port",
+    "bind",
+    "base_path",
+    "cors_origins",
+    "identity_name",
+    "identity_max_age",
+    "identity_secure",
+    "identity_key",
+    "log_level",
+];
+
+/// runtime config of `run_from_config()` generated by `SPAServer`. the
+/// attribute gives the defaults, overridden by the keys of the file at
+/// `SPA_CONFIG`, overridden by the `SPA_*` env vars
+/// ```toml
+/// port = 8080                      # SPA_PORT
+/// bind = "127.0.0.1"               # SPA_BIND
+/// base_path = "/app"               # SPA_BASE_PATH
+/// cors_origins = ["https://a.com"] # SPA_CORS_ORIGINS=https://a.com,https://b.com
+/// identity_name = "session"        # SPA_IDENTITY_NAME
+/// identity_max_age = 60            # SPA_IDENTITY_MAX_AGE, minutes
+/// identity_secure = true           # SPA_IDENTITY_SECURE
+/// identity_key = "..."             # SPA_IDENTITY_KEY, at least 32 bytes
+/// log_level = "info"               # SPA_LOG_LEVEL
+/// ```
+#[derive(Debug, Clone)]
+pub struct RuntimeConfig {
+    pub port: u16,
+    /// ip address or host name of the listener
+    pub bind: String,
+    pub base_path: String,
+    /// None when the `cors` option is off, empty for any origin
+    pub cors_origins: Option<Vec<String>>,
+    /// None when the `identity` option is off
+    pub identity: Option<IdentityConfig>,
+    /// max level of the `log` macros, the logger itself is up to the app
+    pub log_level: Option<LevelFilter>,
+}
+
+/// the identity cookie
+#[derive(Debug, Clone)]
+pub struct IdentityConfig {
+    pub name: String,
+    /// minutes
+    pub max_age: i64,
+    pub secure: bool,
+    /// key signing the cookie, a fixed one when None
+    pub key: Option<String>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig {
+            port: 8080,
+            bind: "0.0.0.0".to_string(),
+            base_path: String::new(),
+            cors_origins: None,
+            identity: None,
+            log_level: None,
+        }
+    }
+}
+
+/// every invalid key of the config, one per line
+pub struct ConfigError(pub Vec<String>);
+
+/// same as Display, so an error returned from main reads well
+impl fmt::Debug for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid spa-server config:")?;
+        for e in &self.0 {
+            write!(f, "\n  {}", e)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for ConfigError {}
+
+impl RuntimeConfig {
+    /// merge the config file and the env vars into `self`, the attribute
+    /// defaults
+    pub fn load(mut self) -> Result<Self, ConfigError> {
+        let mut errors = Vec::new();
+
+        // (where the key comes from, key, value)
+        let mut entries = Vec::new();
+        if let Ok(path) = env::var(CONFIG_FILE_ENV) {
+            match read_file(Path::new(&path)) {
+                Ok(map) => {
+                    for (key, value) in map {
+                        entries.push((format!("{}: {}", path, key), key, value));
+                    }
+                }
+                Err(e) => errors.push(format!("{}: {}", path, e)),
+            }
+        }
+
+        for key in KEYS {
+            let name = format!("{}{}", ENV_PREFIX, key.to_ascii_uppercase());
+            if let Ok(value) = env::var(&name) {
+                entries.push((name, key.to_string(), Value::String(value)));
+            }
+        }
+
+        for (source, key, value) in entries {
+            if let Err(e) = self.set(&key, &value) {
+                errors.push(format!("{}: {}", source, e));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(ConfigError(errors))
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &Value) -> Result<(), String> {
+        match key {
+            "port" => self.port = parse(value, "a port number")?,
+            "bind" => {
+                let bind = string(value)?;
+                if bind.is_empty()
+                    || bind.contains("://")
+                    || (bind.contains(':') && bind.parse::<IpAddr>().is_err())
+                {
+                    return Err(format!("expect an ip address or host name, got {}", bind));
+                }
+                self.bind = bind;
+            }
+            "base_path" => {
+                let base_path = string(value)?;
+                if !base_path.is_empty() && !base_path.starts_with('/') {
+                    return Err(format!("expect a path starting with /, got {}", base_path));
+                }
+                self.base_path = base_path;
+            }
+            "cors_origins" => {
+                let origins = list(value)?;
+                for origin in &origins {
+                    check_origin(origin)?;
+                }
+                match &mut self.cors_origins {
+                    Some(cors_origins) => *cors_origins = origins,
+                    None => return Err("needs the cors option of SPAServer".to_string()),
+                }
+            }
+            "identity_name" | "identity_max_age" | "identity_secure" | "identity_key" => {
+                let identity = self
+                    .identity
+                    .as_mut()
+                    .ok_or("needs the identity option of SPAServer")?;
+                match key {
+                    "identity_name" => {
+                        let name = string(value)?;
+                        if name.is_empty()
+                            || !name
+                                .chars()
+                                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+                        {
+                            return Err(format!("invalid cookie name {}", name));
+                        }
+                        identity.name = name;
+                    }
+                    "identity_max_age" => {
+                        identity.max_age = parse(value, "a number of minutes")?;
+                        if identity.max_age <= 0 {
+                            return Err("expect a positive number of minutes".to_string());
+                        }
+                    }
+                    "identity_secure" => identity.secure = parse(value, "true or false")?,
+                    _ => {
+                        let key = string(value)?;
+                        if key.len() < MIN_IDENTITY_KEY {
+                            return Err(format!(
+                                "expect a key of at least {} bytes",
+                                MIN_IDENTITY_KEY
+                            ));
+                        }
+                        identity.key = Some(key);
+                    }
+                }
+            }
+            "log_level" => {
+                self.log_level = Some(parse(value, "off, error, warn, info, debug or trace")?)
+            }
+            _ => return Err("unknown key".to_string()),
+        }
+
+        Ok(())
+    }
+
+    /// the builder listening on `bind:port` with the rest of the config. the
+    /// log level goes first, then the config is logged with the secrets
+    /// redacted
+    pub fn apply<T: Serve>(&self, builder: ServerBuilder<T>) -> ServerBuilder<T> {
+        if let Some(level) = self.log_level {
+            log::set_max_level(level);
+        }
+        info!("spa-server config: {}", self);
+
+        let mut builder = builder
+            .bind(&self.bind, self.port)
+            .base_path(&self.base_path);
+        for origin in self.cors_origins.iter().flatten() {
+            builder = builder.cors_origin(origin);
+        }
+        if let Some(identity) = &self.identity {
+            builder = builder
+                .identity_name(&identity.name)
+                .identity_max_age(identity.max_age)
+                .identity_secure(identity.secure);
+            if let Some(key) = &identity.key {
+                builder = builder.identity_key(key.as_bytes());
+            }
+        }
+
+        builder
+    }
+}
+
+/// the effective config, secrets redacted
+impl Display for RuntimeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "port = {}, bind = {}", self.port, self.bind)?;
+        write!(f, ", base_path = {:?}", self.base_path)?;
+        if let Some(origins) = &self.cors_origins {
+            if origins.is_empty() {
+                write!(f, ", cors_origins = any")?;
+            } else {
+                write!(f, ", cors_origins = {}", origins.join(","))?;
+            }
+        }
+        if let Some(identity) = &self.identity {
+            write!(
+                f,
+                ", identity_name = {}, identity_max_age = {}, identity_secure = {}, identity_key = {}",
+                identity.name,
+                identity.max_age,
+                identity.secure,
+                if identity.key.is_some() {
+                    "<redacted>"
+                } else {
+                    "<default>"
+                }
+            )?;
+        }
+        if let Some(level) = self.log_level {
+            write!(f, ", log_level = {}", level)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// the top level keys of the TOML or JSON file, told apart by the extension
+fn read_file(path: &Path) -> Result<Map<String, Value>, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let value = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str::<Value>(&content).map_err(|e| e.to_string())?,
+        Some("json") => serde_json::from_str::<Value>(&content).map_err(|e| e.to_string())?,
+        _ => return Err("expect a .toml or .json file".to_string()),
+    };
+
+    match value {
+        Value::Object(map) => Ok(map),
+        _ => Err("expect a table of keys".to_string()),
+    }
+}
+
+/// a string value, numbers and booleans of the file as written
+fn string(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.trim().to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(format!("expect a single value, got {}", value)),
+    }
+}
+
+fn parse<T: FromStr>(value: &Value, expect: &str) -> Result<T, String> {
+    let s = string(value)?;
+    s.parse()
+        .map_err(|_| format!("expect {}, got {}", expect, s))
+}
+
+/// an array of the file, or a comma separated env var
+fn list(value: &Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::Array(values) => values.iter().map(string).collect(),
+        _ => Ok(string(value)?
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()),
+    }
+}
+
+/// scheme, host and optional port, no wildcard and no path
+fn check_origin(origin: &str) -> Result<(), String> {
+    let host = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .ok_or_else(|| format!("expect an http(s):// origin, got {}", origin))?;
+    if host.is_empty() || host.contains('/') || host.contains('*') {
+        return Err(format!(
+            "expect an origin without path or wildcard, got {}",
+            origin
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn with_options() -> RuntimeConfig {
+        RuntimeConfig {
+            cors_origins: Some(Vec::new()),
+            identity: Some(IdentityConfig {
+                name: "id".to_string(),
+                max_age: 30,
+                secure: false,
+                key: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn set_keys() {
+        let mut config = with_options();
+        config.set("port", &json!(9090)).unwrap();
+        config.set("bind", &json!("::1")).unwrap();
+        config.set("base_path", &json!("/app")).unwrap();
+        config
+            .set("cors_origins", &json!("https://a.com, http://b.com:8080"))
+            .unwrap();
+        config.set("identity_name", &json!("session")).unwrap();
+        config.set("identity_max_age", &json!("60")).unwrap();
+        config.set("identity_secure", &json!(true)).unwrap();
+        config.set("identity_key", &json!("k".repeat(32))).unwrap();
+        config.set("log_level", &json!("debug")).unwrap();
+
+        assert_eq!(config.port, 9090);
+        assert_eq!(config.bind, "::1");
+        assert_eq!(config.base_path, "/app");
+        assert_eq!(
+            config.cors_origins.unwrap(),
+            vec!["https://a.com", "http://b.com:8080"]
+        );
+        let identity = config.identity.unwrap();
+        assert_eq!(identity.name, "session");
+        assert_eq!(identity.max_age, 60);
+        assert!(identity.secure);
+        assert_eq!(identity.key.unwrap(), "k".repeat(32));
+        assert_eq!(config.log_level, Some(LevelFilter::Debug));
+    }
+
+    #[test]
+    fn set_invalid() {
+        let mut config = with_options();
+        for (key, value) in &[
+            ("port", json!("http")),
+            ("port", json!(65536)),
+            ("bind", json!("")),
+            ("bind", json!("http://a.com")),
+            ("bind", json!("a.com:80")),
+            ("base_path", json!("app")),
+            ("cors_origins", json!(["https://a.com", "*"])),
+            ("identity_name", json!("a b")),
+            ("identity_max_age", json!(0)),
+            ("identity_secure", json!("yes")),
+            ("identity_key", json!("short")),
+            ("log_level", json!("loud")),
+            ("port", json!([8080])),
+            ("unknown", json!(1)),
+        ] {
+            assert!(config.set(key, value).is_err(), "{} = {}", key, value);
+        }
+
+        let mut config = RuntimeConfig::default();
+        let e = config.set("cors_origins", &json!([])).unwrap_err();
+        assert!(e.contains("cors option"), "{}", e);
+        let e = config.set("identity_name", &json!("id")).unwrap_err();
+        assert!(e.contains("identity option"), "{}", e);
+    }
+
+    #[test]
+    fn origins() {
+        for origin in &[
+            "https://a.com",
+            "http://localhost:3000",
+            "https://[::1]:8443",
+        ] {
+            assert!(check_origin(origin).is_ok(), "{}", origin);
+        }
+        for origin in &[
+            "a.com",
+            "ftp://a.com",
+            "https://",
+            "https://a.com/",
+            "https://*.a.com",
+        ] {
+            assert!(check_origin(origin).is_err(), "{}", origin);
+        }
+    }
+
+    #[test]
+    fn display_redacts_key() {
+        let mut config = with_options();
+        assert!(config.to_string().contains("identity_key = <default>"));
+        assert!(config.to_string().contains("cors_origins = any"));
+
+        let secret = "s".repeat(32);
+        config.set("identity_key", &json!(secret)).unwrap();
+        let shown = config.to_string();
+        assert!(shown.contains("identity_key = <redacted>"), "{}", shown);
+        assert!(!shown.contains(&secret), "{}", shown);
+    }
+
+    /// the only test touching the process env, so the tests running in
+    /// parallel don't see each other's vars
+    #[test]
+    fn load_file_and_env() {
+        let path = env::temp_dir().join(format!("spa-server-config-{}.json", std::process::id()));
+        fs::write(
+            &path,
+            json!({"port": 7070, "bind": "127.0.0.1", "log_level": "warn"}).to_string(),
+        )
+        .unwrap();
+        env::set_var(CONFIG_FILE_ENV, &path);
+        env::set_var("SPA_PORT", "9090");
+        // not a config key, e.g. of the app
+        env::set_var("SPA_API_URL", "https://api.a.com");
+
+        let config = RuntimeConfig::default().load().unwrap();
+        // the env var wins over the file
+        assert_eq!(config.port, 9090);
+        assert_eq!(config.bind, "127.0.0.1");
+        assert_eq!(config.log_level, Some(LevelFilter::Warn));
+
+        fs::write(&path, json!({"bind": "a b:c", "prot": 1}).to_string()).unwrap();
+        env::set_var("SPA_IDENTITY_NAME", "session");
+        let e = RuntimeConfig::default().load().unwrap_err();
+        assert_eq!(e.0.len(), 3, "{}", e);
+        assert!(
+            e.0.iter().any(|e| e.starts_with("SPA_IDENTITY_NAME: ")),
+            "{}",
+            e
+        );
+
+        let toml = path.with_extension("toml");
+        fs::write(&toml, "port = 6060\ncors_origins = [\"https://a.com\"]\n").unwrap();
+        env::set_var(CONFIG_FILE_ENV, &toml);
+        env::remove_var("SPA_PORT");
+        env::remove_var("SPA_IDENTITY_NAME");
+        let config = RuntimeConfig {
+            cors_origins: Some(Vec::new()),
+            ..Default::default()
+        }
+        .load()
+        .unwrap();
+        assert_eq!(config.port, 6060);
+        assert_eq!(config.cors_origins.unwrap(), vec!["https://a.com"]);
+
+        env::set_var(CONFIG_FILE_ENV, path.with_extension("yaml"));
+        assert!(RuntimeConfig::default().load().is_err());
+
+        env::remove_var(CONFIG_FILE_ENV);
+        env::remove_var("SPA_API_URL");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&toml);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{asset::to_bytes, template::render_index, Embed};
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::StatusCode,
+    web, Error, HttpMessage, HttpRequest, HttpResponse,
+};
+use std::{borrow::Cow, future::Future, path::Path};
+
+/// embedded pages answered for the 404 and 5xx errors of the non api requests
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct ErrorPages {
+    get: fn(&str) -> Option<Cow<'static, [u8]>>,
+    content_type: fn(&str) -> String,
+    not_found: Option<&'static str>,
+    server_error: Option<&'static str>,
+}
+
+impl ErrorPages {
+    /// pages are relative paths in the assets folder of `T`
+    pub fn new<T: Embed>(
+        not_found: Option<&'static str>,
+        server_error: Option<&'static str>,
+    ) -> Self {
+        ErrorPages {
+            get: T::get,
+            content_type: T::content_type,
+            not_found,
+            server_error,
+        }
+    }
+
+    fn page(&self, status: StatusCode) -> Option<(&'static str, Cow<'static, [u8]>)> {
+        let page = match status {
+            StatusCode::NOT_FOUND => self.not_found,
+            status if status.is_server_error() => self.server_error,
+            _ => None,
+        }?;
+
+        (self.get)(page).map(|data| (page, data))
+    }
+}
+
+/// request extension marks a response whose errors keep their own body
+#[derive(Clone, Copy)]
+struct KeepBody;
+
+/// the errors of `req` are answered as they are, not with the error pages
+pub(crate) fn keep_body(req: &HttpRequest) {
+    req.extensions_mut().insert(KeepBody);
+}
+
+/// `wrap_fn` middleware marks the requests of an api, it wraps every resource
+/// of the route macros so their json errors are kept whatever the prefix and
+/// the fallback excludes
+#[doc(hidden)]
+pub fn api_marker<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    keep_body(req.request());
+    srv.call(req)
+}
+
+/// `wrap_fn` middleware replaces the body of the 404 and 5xx responses with
+/// the embedded error page, unless they come from an api, the dev proxy or
+/// the json 404 of the fallback excludes. html pages go through the index
+/// template like the SPA pages
+#[doc(hidden)]
+pub fn error_page<S>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = Error>,
+{
+    let fut = srv.call(req);
+    async move {
+        let resp = fut.await?;
+        if resp.request().extensions().get::<KeepBody>().is_some() {
+            return Ok(resp);
+        }
+
+        Ok(match render(resp.request(), resp.status()) {
+            Some(page) => resp.into_response(page),
+            None => resp,
+        })
+    }
+}
+
+/// the error page of `status` if any
+fn render(req: &HttpRequest, status: StatusCode) -> Option<HttpResponse> {
+    let pages = req.app_data::<web::Data<ErrorPages>>()?;
+    let (page, data) = pages.page(status)?;
+
+    let ext = Path::new(page)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    let rendered = if ext == "html" {
+        render_index(req, &data, None)
+    } else {
+        None
+    };
+    let mut resp = rendered.unwrap_or_else(|| {
+        HttpResponse::Ok()
+            .content_type((pages.content_type)(page))
+            .body(to_bytes(data))
+    });
+
+    *resp.status_mut() = status;
+    Some(resp)
+}
@@ -0,0 +1,171 @@
+use crate::{error_page::keep_body, quick_err};
+use actix_files::file_extension_to_mime;
+use actix_web::{http::header::ACCEPT, web, HttpRequest, HttpResponse};
+use std::path::Path;
+
+/// how the SPA fallback tells a client side route from a missing file
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub enum Matcher {
+    /// the path has no dot at all
+    NoDot,
+    /// the last path segment has no known file extension, so `/users/john.doe`
+    /// is still a route while `/logo2.png` is a missing file. the default
+    NoExtension,
+    /// the client accepts text/html, i.e. a browser navigation
+    AcceptHtml,
+    /// every path without file
+    All,
+}
+
+/// SPA fallback rules, answer index.html for the client side routes unless the
+/// path is under one of the excluded prefixes
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub struct Fallback {
+    exclude: Vec<String>,
+    matcher: Matcher,
+}
+
+impl Default for Fallback {
+    fn default() -> Self {
+        Fallback {
+            exclude: Vec::new(),
+            matcher: Matcher::NoExtension,
+        }
+    }
+}
+
+impl Fallback {
+    pub fn new(exclude: Vec<String>, matcher: Matcher) -> Self {
+        let exclude = exclude
+            .into_iter()
+            .map(|p| p.trim_end_matches('/').to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+
+        Fallback { exclude, matcher }
+    }
+
+    /// whether the url path is under an excluded prefix, which answers json 404
+    pub fn is_excluded(&self, url_path: &str) -> bool {
+        self.exclude.iter().any(|p| {
+            url_path
+                .strip_prefix(p.as_str())
+                .map(|rest| rest.is_empty() || rest.starts_with('/'))
+                .unwrap_or(false)
+        })
+    }
+
+    /// whether the request for the missing `file_path` is a client side route
+    pub fn matches(&self, req: &HttpRequest, file_path: &str) -> bool {
+        match self.matcher {
+            Matcher::NoDot => !file_path.contains('.'),
+            Matcher::NoExtension => match Path::new(file_path).extension() {
+                Some(ext) => {
+                    file_extension_to_mime(&ext.to_string_lossy()).to_string()
+                        == "application/octet-stream"
+                }
+                None => true,
+            },
+            Matcher::AcceptHtml => req
+                .headers()
+                .get(ACCEPT)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.contains("text/html"))
+                .unwrap_or(false),
+            Matcher::All => true,
+        }
+    }
+}
+
+/// fallback rules registered in the app, or the default ones
+pub(crate) fn fallback_of(req: &HttpRequest) -> Fallback {
+    req.app_data::<web::Data<Fallback>>()
+        .map(|f| f.get_ref().clone())
+        .unwrap_or_default()
+}
+
+/// default service of the app, answers json 404 for the excluded prefixes, and
+/// a bare 404 for the others, which gets the error page if any
+#[doc(hidden)]
+pub async fn not_found(req: HttpRequest) -> HttpResponse {
+    if fallback_of(&req).is_excluded(req.path()) {
+        json_not_found(&req)
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+pub(crate) fn json_not_found(req: &HttpRequest) -> HttpResponse {
+    keep_body(req);
+    HttpResponse::NotFound().json(quick_err(format!("{} not found", req.path())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn matches(matcher: Matcher, file_path: &str) -> bool {
+        let req = TestRequest::default().to_http_request();
+        Fallback::new(Vec::new(), matcher).matches(&req, file_path)
+    }
+
+    #[test]
+    fn excluded_on_segment_boundary() {
+        let fallback = Fallback::new(
+            vec!["/api".to_string(), "/internal/".to_string()],
+            Matcher::All,
+        );
+        assert!(fallback.is_excluded("/api"));
+        assert!(fallback.is_excluded("/api/"));
+        assert!(fallback.is_excluded("/api/v1/users"));
+        assert!(!fallback.is_excluded("/apix"));
+        assert!(!fallback.is_excluded("/app/api"));
+        // the trailing slash of a prefix is dropped
+        assert!(fallback.is_excluded("/internal"));
+        assert!(!fallback.is_excluded("/internals"));
+    }
+
+    #[test]
+    fn empty_prefix_excludes_nothing() {
+        let fallback = Fallback::new(vec!["/".to_string(), String::new()], Matcher::All);
+        assert!(!fallback.is_excluded("/"));
+        assert!(!fallback.is_excluded("/about"));
+    }
+
+    #[test]
+    fn no_extension() {
+        assert!(matches(Matcher::NoExtension, "users/john.doe"));
+        assert!(matches(Matcher::NoExtension, "users/john"));
+        assert!(!matches(Matcher::NoExtension, "logo2.png"));
+        assert!(!matches(Matcher::NoExtension, "js/main.js"));
+    }
+
+    #[test]
+    fn no_dot() {
+        assert!(matches(Matcher::NoDot, "users/john"));
+        assert!(!matches(Matcher::NoDot, "users/john.doe"));
+    }
+
+    #[test]
+    fn accept_html() {
+        let fallback = Fallback::new(Vec::new(), Matcher::AcceptHtml);
+        let browser = TestRequest::default()
+            .insert_header((ACCEPT, "text/html,application/xhtml+xml,*/*;q=0.8"))
+            .to_http_request();
+        let fetch = TestRequest::default()
+            .insert_header((ACCEPT, "application/json"))
+            .to_http_request();
+        assert!(fallback.matches(&browser, "logo2.png"));
+        assert!(!fallback.matches(&fetch, "users/john"));
+    }
+
+    #[test]
+    fn default_matcher() {
+        let req = TestRequest::default().to_http_request();
+        assert!(Fallback::default().matches(&req, "users/john.doe"));
+        assert!(!Fallback::default().matches(&req, "logo2.png"));
+    }
+}
@@ -0,0 +1,43 @@
+use actix_web::dev::Server;
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// handle of a server returned by `start()` generated by `SPAServer`, the
+/// server runs in the background and awaiting the handle waits until it stops
+#[derive(Clone)]
+pub struct ServerHandle {
+    server: Server,
+    addrs: Vec<SocketAddr>,
+}
+
+impl ServerHandle {
+    #[doc(hidden)]
+    pub fn new(server: Server, addrs: Vec<SocketAddr>) -> Self {
+        ServerHandle { server, addrs }
+    }
+
+    /// the bound tcp and https addresses, with the actual port of a `:0`
+    /// listener. unix domain sockets are not listed
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// stop accepting connections, a graceful stop lets the in-flight requests
+    /// finish within the shutdown timeout, otherwise they are dropped
+    pub async fn stop(&self, graceful: bool) {
+        self.server.stop(graceful).await
+    }
+}
+
+impl Future for ServerHandle {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.server).poll(cx)
+    }
+}
@@ -20,7 +20,30 @@
 //!         api(test),                  # api without prefix
 //!     ),
 //!     cors,                           # enable cors permissive for debug
-//!     identity(name = "a", age = 30)  # identity support, cookie name and age in minutes
+//!     identity(name = "a", age = 30), # identity support, cookie name and age in minutes
+//!     cache(                          # Cache-Control policy, glob or "regex:..." rules
+//!         immutable = "**/*.[hash].*",# fingerprinted files, cached forever
+//!         no_cache = "index.html",    # always revalidated
+//!         max_age = 3600,             # max-age in seconds for other files
+//!     ),
+//!     exclude("**/.DS_Store"),        # files not packed into binary, include(...) also works
+//!     strip_source_maps,              # don't pack *.map files in release build
+//!     assets(                         # more asset dirs, each one served at its own url path
+//!         mount(dir = "docs/site", at = "/docs"),
+//!         spa(dir = "admin/dist", at = "/admin", index = "index.html"), # another SPA with its own index page
+//!     ),
+//!     mime(ext = "wasm", type = "application/wasm"), # override mime type of a file extension
+//!     extract(dir = "/var/lib/app"),  # release files on disk instead of serving from memory, dir defaults to temp dir
+//!     manifest = "/__spa/manifest",   # serve Server::asset_manifest() in json
+//!     fallback(exclude("/api"), matcher = "accept_html"), # index.html fallback rules, apis are excluded by default, the matcher is no_extension by default
+//!     config(method = "spa_config", env_prefix = "APP_"), # replace <!--SPA_CONFIG--> in index.html with window.__CONFIG__
+//!     csp(policy = "script-src 'self' 'nonce-{nonce}'"), # per request nonce on index.html scripts and styles, a bare csp uses a strict default
+//!     error_pages(not_found = "404.html", server_error = "500.html"), # error pages in static_files, body of any 404 and 5xx response but the api ones
+//!     base_path = "/tools/reporting", # serve everything under this path and rewrite <base href>, run_with_base_path() sets it at runtime
+//!     pwa(service_worker = "sw.js", scope = "/", precache = "/precache-manifest.json"), # no-cache service worker with Service-Worker-Allowed, manifest mime for .webmanifest, workbox precache manifest
+//!     build_id(path = "/build-id", header = "x-build-id"), # build hash of the embedded files as json and on every api response, to detect a new version
+//!     tls(cert = "/etc/app/cert.pem", key = "/etc/app/key.pem", reload), # https with rustls (tls feature), PEM files read at startup and again on SIGHUP
+//!     shutdown_timeout = 30, # seconds a graceful stop, SIGTERM included, waits for the in-flight requests
 //! )]
 //! pub struct Server {
 //!     data: String,
@@ -50,6 +73,20 @@
 //!
 //! ```
 //! access http://localhost:8080 will show the SPA index.html page
+//!
+//! for the client side routes, pre-rendered pages are served before the SPA index.html, `/about` serves `about.html` or `about/index.html` if any
+//!
+//! in development, `SPA_DEV_PROXY=5173` (or `Server { .. }.run_with_dev_proxy(8080, 5173)`) forwards all the requests but the apis to the frontend dev server at 127.0.0.1:5173, hot module reload websocket included
+//!
+//! `run(8080)` listens on 0.0.0.0:8080, `Server { .. }.run_with_listeners(&["tcp://127.0.0.1:8080", "tcp://[::]:8080", "unix:///run/app.sock"])` serves the same app on each of the listeners
+//!
+//! with the `tls` feature, `https://0.0.0.0:8443` listeners terminate TLS with the certificate of the attribute, or of `Server { .. }.run_with_tls(&["https://0.0.0.0:8443"], TlsConfig::new("cert.pem", "key.pem").reload_on_sighup(true))`
+//!
+//! `Server { .. }.start(0)` (or `start_with_listeners`) returns once the server listens, the `ServerHandle` gives the bound addresses, `stop(graceful)` and is awaited until the server ends
+//!
+//! `Server { .. }.builder()` tunes the server at runtime: `.port(8080)`, `.listen("unix:///run/app.sock")`, `.tls(...)`, `.base_path(...)`, `.workers(4)`, `.keep_alive(...)`, `.client_timeout(...)`, `.client_shutdown(...)`, `.max_connections(...)`, `.max_connection_rate(...)`, `.backlog(...)`, `.payload_limit(...)`, `.shutdown_timeout(...)`, then `.run().await` or `.start()`, `server.run(8080)` is the shortcut of `server.builder().port(8080).run()`
+//!
+//! `Server { .. }.run_from_config()` takes the port, bind address, base path, cors origins, identity cookie and log level from the `SPA_*` env vars (`SPA_PORT`, `SPA_BIND`, `SPA_CORS_ORIGINS`, ...) and the TOML or JSON file at `SPA_CONFIG`, over the attribute, see `RuntimeConfig`
 
 /// re-export all the pub symbols from actix-web, no need to add additional
 /// [actix-web](https://crates.io/crates/actix-web) dependency in Cargo.toml.
@@ -97,68 +134,213 @@ pub use spa_server_derive::SPAServer;
 #[doc(hidden)]
 pub use time::Duration;
 
+mod asset;
+#[doc(hidden)]
+pub use asset::{asset_service, base_path, base_redirect, extracted_service, spa_service};
+
+mod builder;
+#[doc(hidden)]
+pub use builder::{Serve, ServerSettings};
+pub use builder::ServerBuilder;
+
+mod build_id;
+#[doc(hidden)]
+pub use build_id::{build_id_header, build_id_service, BuildId};
+pub use build_id::BUILD_ID_HEADER;
+
+mod config;
+pub use config::{ConfigError, IdentityConfig, RuntimeConfig, CONFIG_FILE_ENV};
+
+mod error_page;
+#[doc(hidden)]
+pub use error_page::{api_marker, error_page, ErrorPages};
+
+mod fallback;
+#[doc(hidden)]
+pub use fallback::{not_found, Fallback, Matcher};
+
+mod handle;
+pub use handle::ServerHandle;
+
+mod listener;
+pub use listener::Listener;
+
+mod manifest;
+#[doc(hidden)]
+pub use manifest::{manifest_entries, manifest_service};
+pub use manifest::{AssetEntry, AssetManifest};
+
+mod proxy;
+#[doc(hidden)]
+pub use proxy::{dev_proxy_from_env, proxy_service};
+pub use proxy::DEV_PROXY_ENV;
+
+mod pwa;
+#[doc(hidden)]
+pub use pwa::{precache_service, Pwa};
+
+mod tls;
+#[cfg(feature = "tls")]
+#[doc(hidden)]
+pub use tls::https_config;
+pub use tls::TlsConfig;
+
+mod template;
+#[doc(hidden)]
+pub use template::IndexTemplate;
+pub use template::{CONFIG_PLACEHOLDER, DEFAULT_CSP};
+
 use log::{debug, warn};
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use re_export::*;
+use regex::Regex;
 use serde::Serialize;
 use std::{
+    any::type_name,
     borrow::{Borrow, Cow},
     collections::HashMap,
     env::temp_dir,
-    fs::create_dir_all,
-    path::{Path, PathBuf},
+    fs::{create_dir_all, read_dir, remove_dir_all, remove_file},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
+
+/// asset folder released on disk, removed when the last holder drops it, i.e.
+/// after the last server serving it is gracefully shut down
 #[doc(hidden)]
-#[actix_web::get("/{tail:[^\\.]+}")]
-async fn index(path: web::Data<PathBuf>) -> actix_web::Result<NamedFile> {
-    Ok(NamedFile::open(path.join("index.html"))?)
+pub struct ExtractedAssets {
+    dir: PathBuf,
+    /// marker of this holder in the holders folder next to `dir`
+    holder: PathBuf,
+}
+
+impl ExtractedAssets {
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
 }
 
+impl Drop for ExtractedAssets {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.holder);
+        let holders = holders_of(&self.dir);
+        if live_holders(&holders) > 0 {
+            debug!(
+                "keep asset dir {}, still in use by another server",
+                self.dir.to_string_lossy()
+            );
+            return;
+        }
+
+        let _ = remove_dir_all(&holders);
+        match remove_dir_all(&self.dir) {
+            Ok(_) => debug!("removed asset dir: {}", self.dir.to_string_lossy()),
+            Err(e) => warn!(
+                "remove asset dir {} failed: {}",
+                self.dir.to_string_lossy(),
+                e
+            ),
+        }
+    }
+}
 
+/// folder of the markers of the servers using the extracted folder `dir`, one
+/// `<pid>.<n>` file each. it's kept out of `dir` so it's never served
+fn holders_of(dir: &Path) -> PathBuf {
+    let mut name = dir.file_name().unwrap_or_default().to_os_string();
+    name.push(".holders");
+    dir.with_file_name(name)
+}
+
+/// number of markers left in `holders`. on linux the markers of the processes
+/// gone, e.g. killed before the shutdown, are dropped on the way, elsewhere
+/// every marker counts
+fn live_holders(holders: &Path) -> usize {
+    let proc = Path::new("/proc/self").exists();
+    read_dir(holders)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let pid = name.split('.').next().unwrap_or_default();
+            let alive = !proc || Path::new("/proc").join(pid).exists();
+            if !alive {
+                let _ = remove_file(entry.path());
+            }
+
+            alive
+        })
+        .count()
+}
+
+/// release the embedded files into `dir` (temp dir by default). the target
+/// folder is named after the build hash of the embedded files, so a restarted
+/// server reuses the files left there when they are byte-for-byte equal to
+/// the embedded data, the others are written again. the servers sharing the
+/// folder, in this process or another one, each hold a marker and the last
+/// one removes the folder. any write failure fails the startup.
 #[doc(hidden)]
-pub fn release_asset<T>() -> Result<PathBuf>
+pub fn release_asset<T>(dir: Option<&str>) -> std::io::Result<ExtractedAssets>
 where
     T: Embed,
 {
-    let target_dir = temp_dir().join(
-        thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(8)
-            .map(char::from)
-            .collect::<String>(),
-    );
-    if !target_dir.exists() {
-        create_dir_all(&target_dir)?;
-    }
+    static HOLDERS: AtomicUsize = AtomicUsize::new(0);
 
-    debug!("release asset target dir: {}", target_dir.to_string_lossy());
+    let type_name = type_name::<T>().rsplit("::").next().unwrap_or_default();
+    let key = match T::build_hash() {
+        Some(hash) => &hash[..16],
+        None => "dev",
+    };
+    let target_dir = dir
+        .map(PathBuf::from)
+        .unwrap_or_else(temp_dir)
+        .join(format!("spa-server-{}-{}", type_name, key));
+
+    // the marker goes first, so a server shutting down meanwhile keeps the
+    // folder
+    let holders = holders_of(&target_dir);
+    create_dir_all(&holders)?;
+    let holder = holders.join(format!(
+        "{}.{}",
+        process::id(),
+        HOLDERS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&holder, b"")?;
+    let assets = ExtractedAssets {
+        dir: target_dir,
+        holder,
+    };
+    create_dir_all(&assets.dir)?;
+
+    debug!("release asset target dir: {}", assets.dir.to_string_lossy());
 
     for file in T::iter() {
-        match T::get(file.borrow()) {
-            None => {
-                warn!("assert file {} not found", file);
-            }
-            Some(p) => {
-                if let Some(i) = Path::new(file.as_ref()).parent() {
-                    let sub_dir = target_dir.join(i);
-                    create_dir_all(sub_dir)?;
-                } else {
-                    warn!("no parent part for file {}", file);
-                    continue;
-                }
-
-                let path = target_dir.join(file.as_ref());
-                debug!("release asset file: {}", path.to_string_lossy());
-                if let Err(e) = std::fs::write(path, p) {
-                    warn!("asset file {} write failed: {}", file, e);
-                }
-            }
+        let data = T::get(file.borrow()).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("asset file {} not found", file),
+            )
+        })?;
+
+        let path = assets.dir.join(file.as_ref());
+        if std::fs::read(&path).map(|d| d == *data).unwrap_or(false) {
+            debug!("reuse asset file: {}", path.to_string_lossy());
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
         }
+
+        debug!("release asset file: {}", path.to_string_lossy());
+        std::fs::write(&path, data)?;
     }
 
-    Ok(target_dir)
+    Ok(assets)
 }
+
 #[doc(hidden)]
 pub trait Embed {
     /// Given a relative path from the assets folder, returns the bytes if found.
@@ -171,6 +353,36 @@ pub trait Embed {
     /// `Cow::Owned(Vec<u8>)` is returned.
     fn get(file_path: &str) -> Option<Cow<'static, [u8]>>;
 
+    /// Given a relative path from the assets folder, returns the bytes of the
+    /// precompressed variant in `encoding` if found.
+    ///
+    /// Variants are produced at compile time for the compressible files
+    /// (html, css, js, json, svg...) and only kept when smaller than the file.
+    fn get_encoded(file_path: &str, encoding: Encoding) -> Option<&'static [u8]>;
+
+    /// Given a relative path from the assets folder, returns the metadata
+    /// computed at compile time if found.
+    fn metadata(file_path: &str) -> Option<Metadata>;
+
+    /// Given a relative path from the assets folder, returns the value of the
+    /// Content-Type header by its extension.
+    ///
+    /// The `mime(...)` overrides and the defaults of the compile time table
+    /// apply, so files read from the file system or extracted on disk get the
+    /// same type as the embedded ones.
+    fn content_type(file_path: &str) -> String;
+
+    /// Given a client side route, returns the pre-rendered page serving it:
+    /// `<route>.html` first, then `<route>/index.html`.
+    ///
+    /// The lookup table is built at compile time from the embedded files, or
+    /// the file system is probed when the files are read from there.
+    fn page(route: &str) -> Option<Cow<'static, str>>;
+
+    /// Hash of all the embedded files, changes whenever any file is added,
+    /// removed or modified. `None` when the files are read from the file system.
+    fn build_hash() -> Option<&'static str>;
+
     /// Iterates the files in this assets folder.
     ///
     /// If the feature `debug-embed` is enabled or the binary is compiled in
@@ -180,15 +392,164 @@ pub trait Embed {
     /// Otherwise, the files are listed from the file system on each call.
     fn iter() -> Filenames;
 }
+/// compile time metadata of an embedded file
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    /// hex encoded sha256 of the raw file content
+    pub hash: &'static str,
+    /// mime type from the file extension, can be overridden by `mime(...)`
+    pub mime: &'static str,
+    /// charset of the textual types
+    pub charset: Option<&'static str>,
+    /// size of the raw file content
+    pub size: usize,
+    /// size of the smallest precompressed variant
+    pub compressed_size: Option<usize>,
+    /// modification time of the source file, in seconds since unix epoch
+    pub last_modified: Option<u64>,
+    /// Cache-Control header value resolved from the `cache(...)` rules
+    pub cache_control: Option<&'static str>,
+}
+
+impl Metadata {
+    /// value of the Content-Type header
+    pub fn content_type(&self) -> String {
+        match self.charset {
+            Some(charset) => format!("{}; charset={}", self.mime, charset),
+            None => self.mime.to_string(),
+        }
+    }
+}
+
+/// Content-Type of a file by its extension, `table` (the `mime(...)`
+/// overrides and defaults) goes before the actix-files guess, and textual
+/// types get the same charset as in the compile time metadata
+#[doc(hidden)]
+pub fn content_type(file_path: &str, table: &[(&str, &str)]) -> String {
+    let ext = Path::new(file_path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    let mime = match table.iter().find(|(e, _)| *e == ext) {
+        Some((_, mime)) => mime.to_string(),
+        None => actix_files::file_extension_to_mime(&ext)
+            .essence_str()
+            .to_string(),
+    };
+
+    if mime.starts_with("text/")
+        || mime.ends_with("javascript")
+        || mime.ends_with("json")
+        || mime.ends_with("xml")
+    {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime
+    }
+}
+
+/// content coding of a precompressed embedded file
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// the token used in `Accept-Encoding` and `Content-Encoding` headers
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+}
+
 #[doc(hidden)]
-pub struct Filenames(pub std::slice::Iter<'static, &'static str>);
+pub enum Filenames {
+    /// release builds (or `debug-embed`): a static array of the embedded files
+    Embedded(std::slice::Iter<'static, &'static str>),
+    /// debug builds: the files listed from the file system
+    Dynamic(std::vec::IntoIter<String>),
+}
 
 impl Iterator for Filenames {
     type Item = Cow<'static, str>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|x| Cow::from(*x))
+        match self {
+            Filenames::Embedded(names) => names.next().map(|x| Cow::from(*x)),
+            Filenames::Dynamic(names) => names.next().map(Cow::from),
+        }
+    }
+}
+
+/// include/exclude patterns of the static_files folder, checked at runtime in
+/// debug builds. the patterns are translated and validated by the derive macro
+#[doc(hidden)]
+pub struct Filter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl Filter {
+    pub fn new(include: &[&str], exclude: &[&str]) -> Self {
+        let compile = |patterns: &[&str]| {
+            patterns
+                .iter()
+                .map(|p| Regex::new(p).expect("pattern is validated at compile time"))
+                .collect()
+        };
+
+        Filter {
+            include: compile(include),
+            exclude: compile(exclude),
+        }
     }
+
+    pub fn matches(&self, rel_path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|r| r.is_match(rel_path)))
+            && !self.exclude.iter().any(|r| r.is_match(rel_path))
+    }
+}
+
+/// read a file of the static_files folder at runtime, used by the derived
+/// `Embed` in debug builds. paths escaping the folder are rejected
+#[doc(hidden)]
+pub fn read_file(folder: &str, file_path: &str, filter: &Filter) -> Option<Cow<'static, [u8]>> {
+    let rel = Path::new(file_path);
+    if rel.components().any(|c| !matches!(c, Component::Normal(_))) || !filter.matches(file_path) {
+        return None;
+    }
+
+    std::fs::read(Path::new(folder).join(rel))
+        .ok()
+        .map(Cow::from)
+}
+
+/// list all the files of the static_files folder at runtime, relative to the
+/// folder with `/` as separator
+#[doc(hidden)]
+pub fn list_files(folder: &str, filter: &Filter) -> Filenames {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<String>) {
+        for entry in read_dir(dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                walk(root, &path, files);
+            } else if let Ok(rel) = path.strip_prefix(root) {
+                files.push(rel.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    walk(Path::new(folder), Path::new(folder), &mut files);
+    files.retain(|f| filter.matches(f));
+    files.sort();
+    Filenames::Dynamic(files.into_iter())
 }
 #[doc(hidden)]
 #[derive(Serialize)]
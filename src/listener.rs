@@ -0,0 +1,199 @@
+use std::{
+    fmt::{self, Display},
+    io,
+    path::PathBuf,
+    str::FromStr,
+};
+
+/// an address the server listens on, see `run_with_listeners()` generated by
+/// `SPAServer`. parsed from `tcp://127.0.0.1:8080`, `tcp://[::]:8080`,
+/// `https://0.0.0.0:8443` or `unix:///run/app.sock`, an address without
+/// scheme is tcp
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listener {
+    /// `host:port`, the host may be a name resolving to several addresses
+    Tcp(String),
+    /// tcp with TLS termination, see `TlsConfig`
+    Https(String),
+    /// path of a Unix domain socket
+    Unix(PathBuf),
+}
+
+impl Listener {
+    /// a socket file left by a previous run makes the bind fail, remove it
+    /// once a connection to it is refused. a socket some server still listens
+    /// on is an address in use, anything else at the path is kept and
+    /// reported by the bind
+    #[doc(hidden)]
+    pub fn remove_stale_socket(&self) -> io::Result<()> {
+        #[cfg(unix)]
+        if let Listener::Unix(path) = self {
+            use std::os::unix::{fs::FileTypeExt, net::UnixStream};
+            if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                if metadata.file_type().is_socket() {
+                    match UnixStream::connect(path) {
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                            std::fs::remove_file(path)?
+                        }
+                        Ok(_) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::AddrInUse,
+                                format!("{} is in use by another server", path.display()),
+                            ))
+                        }
+                        Err(e) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::AddrInUse,
+                                format!("{}: {}", path.display(), e),
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Listener {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |msg: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} in listener {}", msg, s),
+            )
+        };
+
+        if let Some(path) = s.strip_prefix("unix://") {
+            if path.is_empty() {
+                return Err(invalid("missing socket path"));
+            }
+
+            if cfg!(not(unix)) {
+                return Err(invalid("unix domain sockets are not supported"));
+            }
+
+            return Ok(Listener::Unix(PathBuf::from(path)));
+        }
+
+        let (addr, https) = match s.strip_prefix("https://") {
+            Some(addr) => (addr, true),
+            None => (s.strip_prefix("tcp://").unwrap_or(s), false),
+        };
+        if addr.contains("://") {
+            return Err(invalid("unknown scheme"));
+        }
+
+        match addr.rfind(':') {
+            Some(i) if i > 0 && addr[i + 1..].parse::<u16>().is_ok() => Ok(if https {
+                Listener::Https(addr.to_string())
+            } else {
+                Listener::Tcp(addr.to_string())
+            }),
+            _ => Err(invalid("expect host:port")),
+        }
+    }
+}
+
+impl Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Listener::Https(addr) => write!(f, "https://{}", addr),
+            Listener::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> io::Result<Listener> {
+        s.parse()
+    }
+
+    #[test]
+    fn parse_tcp() {
+        let tcp = |addr: &str| Listener::Tcp(addr.to_string());
+        assert_eq!(
+            parse("tcp://127.0.0.1:8080").unwrap(),
+            tcp("127.0.0.1:8080")
+        );
+        assert_eq!(parse("tcp://[::]:8080").unwrap(), tcp("[::]:8080"));
+        assert_eq!(parse("[::1]:80").unwrap(), tcp("[::1]:80"));
+        assert_eq!(parse("localhost:0").unwrap(), tcp("localhost:0"));
+    }
+
+    #[test]
+    fn parse_https() {
+        assert_eq!(
+            parse("https://[::]:8443").unwrap(),
+            Listener::Https("[::]:8443".to_string())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_unix() {
+        assert_eq!(
+            parse("unix:///run/app.sock").unwrap(),
+            Listener::Unix(PathBuf::from("/run/app.sock"))
+        );
+        assert!(parse("unix://").is_err());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for s in &[
+            "127.0.0.1",
+            "tcp://[::]",
+            ":8080",
+            "tcp://host:port",
+            "tcp://host:65536",
+            "http://127.0.0.1:8080",
+            "udp://127.0.0.1:8080",
+        ] {
+            let e = parse(s).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{}", s);
+        }
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in &[
+            "tcp://[::]:8080",
+            "https://0.0.0.0:8443",
+            "unix:///run/app.sock",
+        ] {
+            assert_eq!(parse(s).unwrap().to_string(), *s);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_socket() {
+        use std::os::unix::net::UnixListener;
+
+        let path =
+            std::env::temp_dir().join(format!("spa-server-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = Listener::Unix(path.clone());
+
+        // nothing there
+        listener.remove_stale_socket().unwrap();
+
+        let live = UnixListener::bind(&path).unwrap();
+        let e = listener.remove_stale_socket().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AddrInUse);
+        assert!(path.exists());
+
+        // the socket file outlives the listener
+        drop(live);
+        listener.remove_stale_socket().unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,53 @@
+use crate::Embed;
+use actix_web::{web, HttpResponse, Resource};
+use serde::Serialize;
+use std::borrow::Borrow;
+
+/// the embedded UI build, see `asset_manifest()` generated by `SPAServer`
+#[derive(Debug, Serialize)]
+pub struct AssetManifest {
+    /// hash of all the embedded files, `None` when read from disk in debug build
+    pub build_hash: Option<&'static str>,
+    pub files: Vec<AssetEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssetEntry {
+    /// url path the file is served at
+    pub path: String,
+    pub size: usize,
+    /// hex encoded sha256, `None` when read from disk in debug build
+    pub hash: Option<&'static str>,
+    pub mime: String,
+}
+
+#[doc(hidden)]
+pub fn manifest_entries<T: Embed>(at: &str) -> Vec<AssetEntry> {
+    let at = at.trim_end_matches('/');
+    T::iter()
+        .filter_map(|file| {
+            let path = format!("{}/{}", at, file);
+            match T::metadata(file.borrow()) {
+                Some(metadata) => Some(AssetEntry {
+                    path,
+                    size: metadata.size,
+                    hash: Some(metadata.hash),
+                    mime: metadata.content_type(),
+                }),
+                None => T::get(file.borrow()).map(|data| AssetEntry {
+                    path,
+                    size: data.len(),
+                    hash: None,
+                    mime: T::content_type(file.borrow()),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// service answers the asset manifest in json
+#[doc(hidden)]
+pub fn manifest_service(path: &str, manifest: fn() -> AssetManifest) -> Resource {
+    web::resource(path)
+        .route(web::get().to(move || async move { HttpResponse::Ok().json(manifest()) }))
+}
@@ -0,0 +1,305 @@
+use crate::{
+    error_page::keep_body,
+    fallback::{fallback_of, json_not_found},
+};
+use actix_web::{
+    http::{
+        header::{HeaderName, HeaderValue, CONNECTION, UPGRADE},
+        StatusCode,
+    },
+    web,
+    web::{Bytes, BytesMut},
+    HttpRequest, HttpResponse, Resource,
+};
+use futures_util::{stream, Stream, StreamExt};
+use log::{debug, warn};
+use std::{env, io, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+/// env var enables the dev proxy, the port of the frontend dev server
+pub const DEV_PROXY_ENV: &str = "SPA_DEV_PROXY";
+
+/// headers of a single connection, never forwarded by a proxy
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+const MAX_HEADERS: usize = 64;
+
+/// time the dev server has to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// time the dev server has to send the response head, the body then streams
+/// for as long as it takes, e.g. the server-sent events of hot module reload
+const HEAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// port of the frontend dev server set by `SPA_DEV_PROXY`, if any
+#[doc(hidden)]
+pub fn dev_proxy_from_env() -> Option<u16> {
+    let port = env::var(DEV_PROXY_ENV).ok()?;
+    match port.trim().parse() {
+        Ok(port) => Some(port),
+        Err(_) => {
+            warn!("ignore invalid {}: {}", DEV_PROXY_ENV, port);
+            None
+        }
+    }
+}
+
+/// service forwards every request to the frontend dev server listening at
+/// `127.0.0.1:port`, websocket upgrades (hot module reload) included
+#[doc(hidden)]
+pub fn proxy_service(port: u16) -> Resource {
+    web::resource("/{tail:.*}")
+        .app_data(web::Data::new(DevProxy {
+            addr: format!("127.0.0.1:{}", port),
+        }))
+        .to(proxy)
+}
+
+struct DevProxy {
+    addr: String,
+}
+
+async fn proxy(
+    req: HttpRequest,
+    payload: web::Payload,
+    dev_proxy: web::Data<DevProxy>,
+) -> HttpResponse {
+    // the errors of the dev server are its own
+    keep_body(&req);
+    if fallback_of(&req).is_excluded(req.path()) {
+        return json_not_found(&req);
+    }
+
+    let result = if is_upgrade(&req) {
+        tunnel(&req, payload, &dev_proxy.addr).await
+    } else {
+        forward(&req, payload, &dev_proxy.addr).await
+    };
+
+    result.unwrap_or_else(|e| {
+        warn!("dev proxy {} {} failed: {}", req.method(), req.uri(), e);
+        HttpResponse::BadGateway().body(format!("dev server {}: {}", dev_proxy.addr, e))
+    })
+}
+
+fn is_upgrade(req: &HttpRequest) -> bool {
+    req.headers().contains_key(UPGRADE)
+        && req
+            .headers()
+            .get(CONNECTION)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_ascii_lowercase().contains("upgrade"))
+            .unwrap_or(false)
+}
+
+/// plain request, sent as HTTP/1.0 so the dev server delimits the response
+/// body by closing the connection, no chunked encoding to decode. the body is
+/// streamed back as it comes, so long-polling and event streams work
+async fn forward(
+    req: &HttpRequest,
+    mut payload: web::Payload,
+    addr: &str,
+) -> io::Result<HttpResponse> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        body.extend_from_slice(
+            &chunk.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?,
+        );
+    }
+
+    let mut head = request_head(req, "HTTP/1.0", false);
+    if !body.is_empty() {
+        head.push_str(&format!("content-length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+
+    let mut upstream = connect(addr).await?;
+    upstream.write_all(head.as_bytes()).await?;
+    upstream.write_all(&body).await?;
+
+    let (head, rest) = read_head(&mut upstream).await?;
+    Ok(head.response().streaming(body_stream(rest, upstream)))
+}
+
+/// websocket upgrade, the handshake is forwarded as is, then the bytes are
+/// piped both ways until either side closes
+async fn tunnel(
+    req: &HttpRequest,
+    mut payload: web::Payload,
+    addr: &str,
+) -> io::Result<HttpResponse> {
+    let mut head = request_head(req, "HTTP/1.1", true);
+    head.push_str("\r\n");
+
+    let mut upstream = connect(addr).await?;
+    upstream.write_all(head.as_bytes()).await?;
+
+    let (head, rest) = read_head(&mut upstream).await?;
+    let upgrade = match head.upgrade() {
+        Some(upgrade) => upgrade.clone(),
+        // upgrade refused, the dev server answered a plain response
+        None => return Ok(head.response().body(rest)),
+    };
+
+    debug!("dev proxy tunnel {:?} {}", upgrade, req.uri());
+    let (read, mut write) = upstream.into_split();
+    actix_web::rt::spawn(async move {
+        while let Some(Ok(chunk)) = payload.next().await {
+            if write.write_all(&chunk).await.is_err() {
+                break;
+            }
+        }
+        let _ = write.shutdown().await;
+    });
+
+    Ok(head
+        .response()
+        .upgrade(upgrade)
+        .streaming(body_stream(rest, read)))
+}
+
+async fn connect(addr: &str) -> io::Result<TcpStream> {
+    timeout(CONNECT_TIMEOUT, TcpStream::connect(addr))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))?
+}
+
+/// the response head, and the start of the body received along with it
+async fn read_head(upstream: &mut TcpStream) -> io::Result<(Head, Bytes)> {
+    let read = async {
+        let mut data = BytesMut::new();
+        loop {
+            if upstream.read_buf(&mut data).await? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "incomplete response",
+                ));
+            }
+
+            if let Some(head) = Head::parse(&data)? {
+                let rest = data.split_off(head.len).freeze();
+                return Ok((head, rest));
+            }
+        }
+    };
+
+    timeout(HEAD_TIMEOUT, read)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "response timed out"))?
+}
+
+/// `rest` then everything read from `read` until the dev server closes
+fn body_stream<R>(rest: Bytes, read: R) -> impl Stream<Item = io::Result<Bytes>>
+where
+    R: AsyncRead + Unpin,
+{
+    stream::once(async move { Ok(rest) }).chain(stream::unfold(read, read_chunk))
+}
+
+async fn read_chunk<R>(mut read: R) -> Option<(io::Result<Bytes>, R)>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buf = BytesMut::with_capacity(8 * 1024);
+    match read.read_buf(&mut buf).await {
+        Ok(0) => None,
+        Ok(_) => Some((Ok(buf.freeze()), read)),
+        Err(e) => Some((Err(e), read)),
+    }
+}
+
+/// request line and headers for the dev server, hop-by-hop headers are kept
+/// for a websocket upgrade only
+fn request_head(req: &HttpRequest, version: &str, upgrade: bool) -> String {
+    let mut head = format!("{} {} {}\r\n", req.method(), req.uri(), version);
+    for (name, value) in req.headers() {
+        if !upgrade && HOP_BY_HOP.contains(&name.as_str()) {
+            continue;
+        }
+
+        if let Ok(value) = value.to_str() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+
+    head
+}
+
+/// status line and headers of a dev server response
+struct Head {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    /// length of the head, the body starts right after
+    len: usize,
+}
+
+impl Head {
+    /// None until the whole head is received
+    fn parse(data: &[u8]) -> io::Result<Option<Self>> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut parsed = httparse::Response::new(&mut headers);
+        let len = match parsed
+            .parse(data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        {
+            httparse::Status::Complete(len) => len,
+            httparse::Status::Partial => return Ok(None),
+        };
+
+        let status = StatusCode::from_u16(parsed.code.unwrap_or_default())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let headers = parsed
+            .headers
+            .iter()
+            .filter_map(|h| {
+                Some((
+                    HeaderName::from_bytes(h.name.as_bytes()).ok()?,
+                    HeaderValue::from_bytes(h.value).ok()?,
+                ))
+            })
+            .collect();
+
+        Ok(Some(Head {
+            status,
+            headers,
+            len,
+        }))
+    }
+
+    /// the protocol switched to, if the dev server accepted the upgrade
+    fn upgrade(&self) -> Option<&HeaderValue> {
+        if self.status != StatusCode::SWITCHING_PROTOCOLS {
+            return None;
+        }
+
+        self.headers
+            .iter()
+            .find(|(name, _)| name == UPGRADE)
+            .map(|(_, value)| value)
+    }
+
+    /// response with the same status and the end-to-end headers
+    fn response(&self) -> actix_web::HttpResponseBuilder {
+        let mut resp = HttpResponse::build(self.status);
+        for (name, value) in &self.headers {
+            if !HOP_BY_HOP.contains(&name.as_str()) {
+                resp.append_header((name.clone(), value.clone()));
+            }
+        }
+
+        resp
+    }
+}
@@ -0,0 +1,80 @@
+use crate::AssetManifest;
+use actix_web::{
+    http::header::{HeaderName, HeaderValue, CACHE_CONTROL, CONTENT_TYPE},
+    web, HttpRequest, HttpResponse, Resource,
+};
+use serde::Serialize;
+
+const MANIFEST_MIME: &str = "application/manifest+json";
+
+/// service worker and web app manifest handling
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub struct Pwa {
+    base_path: String,
+    /// url path of the service worker
+    service_worker: String,
+    /// value of the Service-Worker-Allowed header
+    scope: String,
+}
+
+impl Pwa {
+    /// `service_worker` is relative to the root static files, and `scope` to
+    /// the base path, which is the default scope
+    pub fn new(base_path: &str, service_worker: &str, scope: Option<&str>) -> Self {
+        Pwa {
+            base_path: base_path.to_string(),
+            service_worker: format!("{}/{}", base_path, service_worker.trim_start_matches('/')),
+            scope: format!("{}{}", base_path, scope.unwrap_or("/")),
+        }
+    }
+
+    /// the service worker must never be cached, or the browser keeps running
+    /// an outdated one, and the manifest needs its own mime type
+    pub(crate) fn apply(&self, req: &HttpRequest, file_path: &str, resp: &mut HttpResponse) {
+        let headers = resp.headers_mut();
+        if req.path() == self.service_worker {
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+            if let Ok(scope) = HeaderValue::from_str(&self.scope) {
+                headers.insert(HeaderName::from_static("service-worker-allowed"), scope);
+            }
+        }
+
+        if file_path.ends_with(".webmanifest") {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(MANIFEST_MIME));
+        }
+    }
+}
+
+/// pwa settings registered in the app, if any
+pub(crate) fn pwa_of(req: &HttpRequest) -> Option<&Pwa> {
+    req.app_data::<web::Data<Pwa>>().map(|p| p.get_ref())
+}
+
+/// entry of the precache manifest, in the format of workbox
+#[derive(Serialize)]
+struct PrecacheEntry {
+    url: String,
+    revision: Option<&'static str>,
+}
+
+/// service answers the precache manifest in json, every embedded file but the
+/// service worker itself
+#[doc(hidden)]
+pub fn precache_service(path: &str, manifest: fn() -> AssetManifest) -> Resource {
+    web::resource(path).route(web::get().to(move |req: HttpRequest| async move {
+        let pwa = pwa_of(&req);
+        let base_path = pwa.map(|p| p.base_path.as_str()).unwrap_or_default();
+        let entries = manifest()
+            .files
+            .into_iter()
+            .map(|file| PrecacheEntry {
+                url: format!("{}{}", base_path, file.path),
+                revision: file.hash,
+            })
+            .filter(|entry| pwa.map(|p| p.service_worker != entry.url).unwrap_or(true))
+            .collect::<Vec<_>>();
+
+        HttpResponse::Ok().json(entries)
+    }))
+}
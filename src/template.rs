@@ -0,0 +1,252 @@
+use actix_web::{
+    http::header::{CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY_REPORT_ONLY},
+    web, HttpRequest, HttpResponse,
+};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use regex::{NoExpand, Regex};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::env;
+
+/// placeholder in index.html replaced by the config script by default
+pub const CONFIG_PLACEHOLDER: &str = "<!--SPA_CONFIG-->";
+
+/// placeholder of the per request nonce in the CSP policy
+const NONCE_PLACEHOLDER: &str = "{nonce}";
+
+/// CSP policy used by a bare `csp` option
+pub const DEFAULT_CSP: &str = "default-src 'self'; script-src 'self' 'nonce-{nonce}'; \
+    style-src 'self' 'nonce-{nonce}'; object-src 'none'; base-uri 'self'";
+
+/// template step of the SPA index page. it injects the runtime config as
+/// `<script>window.__CONFIG__ = {...}</script>` in place of the placeholder,
+/// rewrites `<base href>` to the base path, and tags every `<script>` and
+/// `<style>` with a per request CSP nonce
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub struct IndexTemplate {
+    placeholder: String,
+    config: Option<Map<String, Value>>,
+    csp: Option<Csp>,
+    base: Option<(String, Regex)>,
+}
+
+#[derive(Debug, Clone)]
+struct Csp {
+    policy: String,
+    report_only: bool,
+    tags: Regex,
+}
+
+impl Default for IndexTemplate {
+    fn default() -> Self {
+        IndexTemplate {
+            placeholder: CONFIG_PLACEHOLDER.to_string(),
+            config: None,
+            csp: None,
+            base: None,
+        }
+    }
+}
+
+impl IndexTemplate {
+    pub fn placeholder(mut self, placeholder: &str) -> Self {
+        self.placeholder = placeholder.to_string();
+        self
+    }
+
+    /// add the environment variables starting with `prefix`, keyed by the rest
+    /// of their name, e.g. `APP_API_URL` becomes `API_URL` with prefix `APP_`
+    pub fn env(mut self, prefix: &str) -> Self {
+        let config = self.config.get_or_insert_with(Map::new);
+        for (key, value) in env::vars() {
+            if let Some(key) = key.strip_prefix(prefix) {
+                if !key.is_empty() {
+                    config.insert(key.to_string(), Value::String(value));
+                }
+            }
+        }
+
+        self
+    }
+
+    /// add the fields of `config`, which must serialize to a json object, they
+    /// override the environment variables of the same name
+    pub fn config<S: Serialize>(mut self, config: &S) -> serde_json::Result<Self> {
+        let map = self.config.get_or_insert_with(Map::new);
+        match serde_json::to_value(config)? {
+            Value::Object(fields) => map.extend(fields),
+            Value::Null => {}
+            _ => {
+                return Err(serde::ser::Error::custom(
+                    "spa config must serialize to a json object",
+                ))
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// send `policy` as Content-Security-Policy (or its report only variant)
+    /// with every `{nonce}` replaced by the nonce of the request
+    pub fn csp(mut self, policy: &str, report_only: bool) -> Self {
+        self.csp = Some(Csp {
+            policy: policy.to_string(),
+            report_only,
+            tags: Regex::new(r"(?i)<(script|style)\b").expect("invalid tag regex"),
+        });
+        self
+    }
+
+    /// rewrite `<base href>` to the base path the app is served under, so
+    /// one build works at any mount point. nothing to do for the root
+    pub fn base_path(mut self, base_path: &str) -> Self {
+        if !base_path.is_empty() {
+            let tag = Regex::new(r#"(?i)<base\s+href\s*=\s*("[^"]*"|'[^']*')"#)
+                .expect("invalid base regex");
+            self.base = Some((format!("{}/", base_path), tag));
+        }
+        self
+    }
+
+    /// whether the index page needs to be rendered at all
+    fn is_empty(&self) -> bool {
+        self.config.is_none() && self.csp.is_none() && self.base.is_none()
+    }
+
+    /// the config script. every `<` is escaped, it only occurs in the json
+    /// strings, so a value can neither close the tag nor look like a tag to
+    /// the base and nonce rewrites that follow
+    fn script(config: &Map<String, Value>) -> String {
+        let json = Value::Object(config.clone())
+            .to_string()
+            .replace('<', "\\u003c");
+        format!("<script>window.__CONFIG__ = {};</script>", json)
+    }
+
+    pub(crate) fn render(&self, html: &str, nonce: Option<&str>) -> String {
+        let mut html = match &self.config {
+            Some(config) => html.replacen(&self.placeholder, &Self::script(config), 1),
+            None => html.to_string(),
+        };
+
+        if let Some((href, tag)) = &self.base {
+            html = tag
+                .replacen(&html, 1, NoExpand(&format!("<base href=\"{}\"", href)))
+                .into_owned();
+        }
+
+        if let (Some(csp), Some(nonce)) = (&self.csp, nonce) {
+            html = csp
+                .tags
+                .replace_all(&html, format!("<$1 nonce=\"{}\"", nonce).as_str())
+                .into_owned();
+        }
+
+        html
+    }
+}
+
+/// template registered in the app, None when the index page is served as is
+pub(crate) fn template_of(req: &HttpRequest) -> Option<&IndexTemplate> {
+    req.app_data::<web::Data<IndexTemplate>>()
+        .map(|t| t.get_ref())
+        .filter(|t| !t.is_empty())
+}
+
+/// random nonce of a request, alphanumeric so it's valid base64 as required
+fn nonce() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(22)
+        .map(char::from)
+        .collect()
+}
+
+/// render the index page through the template, None when the app has no
+/// template so the page is served byte-for-byte. the rendered page depends on
+/// the runtime config and the request, so it gets no ETag and is never
+/// precompressed
+pub(crate) fn render_index(
+    req: &HttpRequest,
+    html: &[u8],
+    cache_control: Option<&str>,
+) -> Option<HttpResponse> {
+    let template = template_of(req)?;
+    let html = String::from_utf8_lossy(html);
+
+    let mut resp = HttpResponse::Ok();
+    if let Some(cache_control) = cache_control {
+        resp.insert_header((CACHE_CONTROL, cache_control));
+    }
+
+    let nonce = template.csp.as_ref().map(|csp| {
+        let nonce = nonce();
+        let header = if csp.report_only {
+            CONTENT_SECURITY_POLICY_REPORT_ONLY
+        } else {
+            CONTENT_SECURITY_POLICY
+        };
+        resp.insert_header((header, csp.policy.replace(NONCE_PLACEHOLDER, &nonce)));
+        nonce
+    });
+
+    Some(
+        resp.content_type("text/html; charset=utf-8")
+            .body(template.render(&html, nonce.as_deref())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const INDEX: &str = "<html><head><base href=\"/\"><!--SPA_CONFIG-->\
+        <style>body{}</style><SCRIPT src=\"main.js\"></SCRIPT></head></html>";
+
+    fn template(config: Value) -> IndexTemplate {
+        IndexTemplate::default()
+            .config(&config)
+            .unwrap()
+            .csp(DEFAULT_CSP, false)
+            .base_path("/tools")
+    }
+
+    #[test]
+    fn render_all_steps() {
+        let html = template(json!({"api": "/api"})).render(INDEX, Some("n0nce"));
+        assert_eq!(
+            html,
+            "<html><head><base href=\"/tools/\">\
+            <script nonce=\"n0nce\">window.__CONFIG__ = {\"api\":\"/api\"};</script>\
+            <style nonce=\"n0nce\">body{}</style>\
+            <SCRIPT nonce=\"n0nce\" src=\"main.js\"></SCRIPT></head></html>"
+        );
+    }
+
+    #[test]
+    fn config_values_stay_data() {
+        let value = "</script><script>alert(1)</script><style><!-- <base href=\"/evil\">";
+        let html = template(json!({ "x": value })).render(INDEX, Some("n0nce"));
+
+        // the tags of the page got the nonce, nothing in the config did
+        assert_eq!(html.matches("nonce=").count(), 3, "{}", html);
+        assert!(html.contains("<base href=\"/tools/\">"), "{}", html);
+        assert!(!html.contains("/evil\">"), "{}", html);
+
+        let start = html.find("window.__CONFIG__ = ").unwrap() + "window.__CONFIG__ = ".len();
+        let end = html[start..].find(";</script>").unwrap() + start;
+        let config: Value = serde_json::from_str(&html[start..end]).unwrap();
+        assert_eq!(config["x"], value);
+    }
+
+    #[test]
+    fn no_nonce_without_csp() {
+        let template = IndexTemplate::default().config(&json!({"a": 1})).unwrap();
+        assert_eq!(
+            template.render("<!--SPA_CONFIG--><script></script>", None),
+            "<script>window.__CONFIG__ = {\"a\":1};</script><script></script>"
+        );
+    }
+}
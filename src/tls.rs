@@ -0,0 +1,276 @@
+use std::path::PathBuf;
+
+/// certificate chain and private key of the `https://` listeners, PEM files
+/// read at startup. needs the `tls` feature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// read the files again on SIGHUP, the new handshakes use the new
+    /// certificate while the open connections go on
+    pub reload_on_sighup: bool,
+}
+
+impl TlsConfig {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        TlsConfig {
+            cert: cert.into(),
+            key: key.into(),
+            reload_on_sighup: false,
+        }
+    }
+
+    pub fn reload_on_sighup(mut self, reload: bool) -> Self {
+        self.reload_on_sighup = reload;
+        self
+    }
+}
+
+#[cfg(feature = "tls")]
+pub use rustls_config::https_config;
+
+#[cfg(feature = "tls")]
+mod rustls_config {
+    use super::TlsConfig;
+    use crate::Listener;
+    use log::{info, warn};
+    use rustls::{
+        internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys},
+        sign::{any_supported_type, CertifiedKey},
+        ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig,
+    };
+    use std::{
+        fs::File,
+        io::{self, BufReader},
+        path::Path,
+        sync::{Arc, RwLock},
+    };
+
+    fn invalid(path: &Path, what: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), what),
+        )
+    }
+
+    fn pem_reader(path: &Path) -> io::Result<BufReader<File>> {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    /// the certificate chain and the first pkcs8 or rsa key of the files
+    pub(super) fn load(tls: &TlsConfig) -> io::Result<CertifiedKey> {
+        let chain = certs(&mut pem_reader(&tls.cert)?)
+            .map_err(|_| invalid(&tls.cert, "invalid PEM certificate"))?;
+        if chain.is_empty() {
+            return Err(invalid(&tls.cert, "no certificate"));
+        }
+
+        let mut keys = pkcs8_private_keys(&mut pem_reader(&tls.key)?)
+            .map_err(|_| invalid(&tls.key, "invalid PEM private key"))?;
+        if keys.is_empty() {
+            keys = rsa_private_keys(&mut pem_reader(&tls.key)?)
+                .map_err(|_| invalid(&tls.key, "invalid PEM private key"))?;
+        }
+        let key = keys
+            .first()
+            .ok_or_else(|| invalid(&tls.key, "no private key"))?;
+        let key = any_supported_type(key)
+            .map_err(|_| invalid(&tls.key, "unsupported private key type"))?;
+
+        Ok(CertifiedKey::new(chain, Arc::new(key)))
+    }
+
+    /// hands out the current certificate to every handshake, swapped on reload
+    pub(super) struct Resolver(pub(super) RwLock<CertifiedKey>);
+
+    impl Resolver {
+        /// read the files again, a failure keeps the current certificate
+        pub(super) fn reload(&self, tls: &TlsConfig) -> io::Result<()> {
+            let key = load(tls)?;
+            if let Ok(mut current) = self.0.write() {
+                *current = key;
+            }
+
+            Ok(())
+        }
+    }
+
+    impl ResolvesServerCert for Resolver {
+        fn resolve(&self, _: ClientHello) -> Option<CertifiedKey> {
+            self.0.read().ok().map(|key| key.clone())
+        }
+    }
+
+    #[cfg(unix)]
+    fn reload_on_sighup(tls: TlsConfig, resolver: Arc<Resolver>) -> io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        actix_web::rt::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match resolver.reload(&tls) {
+                    Ok(_) => info!("reloaded certificate {}", tls.cert.display()),
+                    Err(e) => warn!("keep the current certificate, reload failed: {}", e),
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn reload_on_sighup(_: TlsConfig, _: Arc<Resolver>) -> io::Result<()> {
+        warn!("no SIGHUP to reload the certificate on this platform");
+        Ok(())
+    }
+
+    impl TlsConfig {
+        /// rustls config of the https listeners, a failed reload keeps the
+        /// certificate in use
+        #[doc(hidden)]
+        pub fn server_config(&self) -> io::Result<ServerConfig> {
+            let resolver = Arc::new(Resolver(RwLock::new(load(self)?)));
+            if self.reload_on_sighup {
+                reload_on_sighup(self.clone(), resolver.clone())?;
+            }
+
+            let mut config = ServerConfig::new(NoClientAuth::new());
+            config.cert_resolver = resolver;
+            Ok(config)
+        }
+    }
+
+    /// rustls config shared by the https listeners among `listeners`, so the
+    /// certificate is loaded and reloaded once. None without https listener
+    #[doc(hidden)]
+    pub fn https_config(
+        tls: Option<&TlsConfig>,
+        listeners: &[Listener],
+    ) -> io::Result<Option<ServerConfig>> {
+        if !listeners.iter().any(|l| matches!(l, Listener::Https(_))) {
+            return Ok(None);
+        }
+
+        match tls {
+            Some(tls) => tls.server_config().map(Some),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "https listener without tls certificate",
+            )),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use super::{
+        rustls_config::{https_config, load, Resolver},
+        TlsConfig,
+    };
+    use crate::Listener;
+    use rustls::internal::pemfile::certs;
+    use std::{env::temp_dir, fs, path::PathBuf, sync::RwLock};
+
+    /// PEM files of a new self-signed certificate, and the DER of the
+    /// certificate
+    fn self_signed(name: &str) -> (TlsConfig, Vec<u8>) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let pem = cert.serialize_pem().unwrap();
+        let tls = tls_files(name);
+        fs::write(&tls.cert, &pem).unwrap();
+        fs::write(&tls.key, cert.serialize_private_key_pem()).unwrap();
+
+        let der = certs(&mut pem.as_bytes()).unwrap().remove(0).0;
+        (tls, der)
+    }
+
+    fn tls_files(name: &str) -> TlsConfig {
+        let dir = temp_dir().join(format!("spa-server-tls-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        TlsConfig::new(dir.join("cert.pem"), dir.join("key.pem"))
+    }
+
+    fn cleanup(tls: &TlsConfig) {
+        let _ = fs::remove_dir_all(tls.cert.parent().unwrap());
+    }
+
+    fn current(resolver: &Resolver) -> Vec<u8> {
+        resolver.0.read().unwrap().cert[0].0.clone()
+    }
+
+    #[test]
+    fn load_pkcs8() {
+        let (tls, der) = self_signed("pkcs8");
+        let key = load(&tls).unwrap();
+        assert_eq!(key.cert.len(), 1);
+        assert_eq!(key.cert[0].0, der);
+        cleanup(&tls);
+    }
+
+    #[test]
+    fn load_rsa_fallback() {
+        // no pkcs8 block left, the key is only found by the rsa pass
+        let (tls, der) = self_signed("rsa");
+        let key = fs::read_to_string(&tls.key).unwrap();
+        fs::write(&tls.key, key.replace("PRIVATE KEY", "RSA PRIVATE KEY")).unwrap();
+        assert_eq!(load(&tls).unwrap().cert[0].0, der);
+        cleanup(&tls);
+    }
+
+    #[test]
+    fn load_errors() {
+        let (tls, _) = self_signed("errors");
+        let cert = fs::read(&tls.cert).unwrap();
+
+        fs::write(&tls.key, &cert).unwrap();
+        let e = load(&tls).unwrap_err().to_string();
+        assert!(e.ends_with("no private key"), "{}", e);
+
+        fs::write(&tls.cert, b"").unwrap();
+        let e = load(&tls).unwrap_err().to_string();
+        assert!(e.ends_with("no certificate"), "{}", e);
+
+        let missing = TlsConfig::new(PathBuf::from("/nonexistent/cert.pem"), &tls.key);
+        let e = load(&missing).unwrap_err().to_string();
+        assert!(e.starts_with("/nonexistent/cert.pem"), "{}", e);
+        cleanup(&tls);
+    }
+
+    #[test]
+    fn reload_swaps_certificate() {
+        let (tls, first) = self_signed("reload");
+        let resolver = Resolver(RwLock::new(load(&tls).unwrap()));
+        assert_eq!(current(&resolver), first);
+
+        let (_, second) = self_signed("reload");
+        assert_ne!(first, second);
+        resolver.reload(&tls).unwrap();
+        assert_eq!(current(&resolver), second);
+
+        // a broken file keeps the certificate in use
+        fs::write(&tls.key, b"").unwrap();
+        assert!(resolver.reload(&tls).is_err());
+        assert_eq!(current(&resolver), second);
+        cleanup(&tls);
+    }
+
+    #[test]
+    fn https_listener_needs_certificate() {
+        let tcp = Listener::Tcp("127.0.0.1:0".to_string());
+        let https = Listener::Https("127.0.0.1:0".to_string());
+
+        assert!(https_config(None, &[tcp.clone()]).unwrap().is_none());
+        let e = https_config(None, &[tcp.clone(), https.clone()]).unwrap_err();
+        assert_eq!(e.to_string(), "https listener without tls certificate");
+
+        // the files are only read for an https listener
+        let unused = TlsConfig::new("/nonexistent/cert.pem", "/nonexistent/key.pem");
+        assert!(https_config(Some(&unused), &[tcp]).unwrap().is_none());
+
+        let (tls, _) = self_signed("https");
+        assert!(https_config(Some(&tls), &[https]).unwrap().is_some());
+        cleanup(&tls);
+    }
+}